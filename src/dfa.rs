@@ -1,8 +1,11 @@
-use std::collections::{HashSet, VecDeque,HashMap, BTreeSet, BTreeMap};
+use std::collections::{VecDeque, HashMap, HashSet, BTreeSet};
 use std::borrow::Cow;
+use std::ops::Range;
 use crate::nfa::NFA;
-use crate::token::Token;
+use crate::regex::Regex;
+use crate::token::{Token, CharClass, split_ranges, char_index, index_char};
 use dot;
+use rand::Rng;
 
 pub struct DFA {
     start : u64,
@@ -26,55 +29,43 @@ impl DFA {
         let mut id_to_point : HashMap<u64, Vec<u64>> = HashMap::new();
         let mut nodes : HashMap<u64, DFANode> = HashMap::new();
         let mut num = 0;
-        let mut nodetype = DFANodeType::NonTerminal;
-        let terminal = nfa.get_terminal();
-
-        let mut tmp = nfa.get_epsilon_closure_node(nfa.get_start()).unwrap();
-        if tmp.contains(&terminal) {
-            nodetype = DFANodeType::Terminal;
-        }
-        tmp.sort();
+        let tmp = nfa.get_start_closure();
+        let mut nodetype = DFANodeType::of_points(nfa, &tmp);
         point_map.insert(tmp.clone(), num);
         id_to_point.insert(num, tmp.clone());
         worklist.push_back(num);
         nodes.insert(num, DFANode::new(num, nodetype));
         
+        // every edge of the DFA is labelled with one of these disjoint ranges,
+        // so overlapping classes on the NFA edges are split
+        let ranges = split_ranges(nfa.get_nodes().flat_map(|node| node.get_edges().iter().map(|edge| &edge.0)));
         while !worklist.is_empty() {
             let q_id = worklist.pop_front().unwrap();
-            let q = id_to_point.get(&q_id).unwrap();
-            let mut tmp : HashMap<Token, BTreeSet<u64>> = HashMap::new();
-
-
-            for node in q {
-                nfa.get_node(*node)
-                    .unwrap()
-                    .get_edges()
-                    .iter()
-                    .for_each(|tuple| {
-                        if tuple.0 == Token::Epsilon {
-                            return;
-                        }
-                        if tmp.get(&tuple.0).is_none() {
-                            tmp.insert(tuple.0.clone(), BTreeSet::new());
-                        }
-
-                        let tmp_set = tmp.get_mut(&tuple.0).unwrap();
-
-                        nfa.get_epsilon_closure_node(tuple.1).unwrap()
-                            .iter()
-                            .for_each(|data| {
-                                tmp_set.insert(*data);
-                            })
-                    });
-            }
+            let q = id_to_point.get(&q_id).unwrap().clone();
 
-            for (key, value) in tmp.iter_mut() {
-                let item : Vec<u64> = value.iter().cloned().collect();
-                nodetype = DFANodeType::NonTerminal;
-                if item.contains(&terminal) {
-                    nodetype = DFANodeType::Terminal;
+            for (start, end) in &ranges {
+                let mut tmp_set = BTreeSet::new();
+                for node in &q {
+                    nfa.get_node(*node)
+                        .unwrap()
+                        .get_edges()
+                        .iter()
+                        .filter(|tuple| tuple.0.matches(*start))
+                        .for_each(|tuple| {
+                            nfa.get_epsilon_closure_node(tuple.1).unwrap()
+                                .iter()
+                                .for_each(|data| {
+                                    tmp_set.insert(*data);
+                                })
+                        });
                 }
-                if point_map.get(&item).is_none() {
+                if tmp_set.is_empty() {
+                    continue;
+                }
+
+                let item : Vec<u64> = tmp_set.into_iter().collect();
+                nodetype = DFANodeType::of_points(nfa, &item);
+                if !point_map.contains_key(&item) {
                     num += 1;
                     point_map.insert(item.clone(), num);
                     id_to_point.insert(num, item.clone());
@@ -82,7 +73,7 @@ impl DFA {
                     worklist.push_back(num);
                 }
                 let q_node = nodes.get_mut(&q_id).unwrap();
-                q_node.push(key.clone(), *point_map.get(&item).unwrap());
+                q_node.push(Token::from_range(*start, *end), *point_map.get(&item).unwrap());
             }
         }
 
@@ -94,188 +85,792 @@ impl DFA {
         }
     }
 
-    pub fn scanner(&self, string : &str) -> String {
-        let mut ans = String::new();
-        let mut max_ans = String::new();
+    /// whether the whole `string` is in the language, running from start without restarts
+    pub fn accepts(&self, string : &str) -> bool {
         let mut id = self.start;
-        let mut iter = string.chars();
-        let mut ch = 'a';
-        let mut pos_flag = true;
-        loop {
-            if pos_flag {
-                match iter.next() {
-                    Some(c) => ch = c,
-                    None => break,
+        for ch in string.chars() {
+            match self.step(id, ch) {
+                Some(to) => id = to,
+                None => return false,
+            }
+        }
+        self.is_terminal(id)
+    }
+
+    /// run from start without restarts and return the byte length of the longest
+    /// prefix of `string` which is in the language, the empty prefix included
+    pub fn accepts_prefix(&self, string : &str) -> Option<usize> {
+        self.match_prefix(string).map(|(len, _)| len)
+    }
+
+    /// same as accepts_prefix, but also return the rule id of the terminal node
+    /// the longest prefix ends in
+    pub fn match_prefix(&self, string : &str) -> Option<(usize, usize)> {
+        let mut id = self.start;
+        let mut longest = None;
+        if let DFANodeType::Terminal(rule) = self.nodes.get(&id).unwrap().nodetype {
+            longest = Some((0, rule));
+        }
+        for (i, ch) in string.char_indices() {
+            match self.step(id, ch) {
+                Some(to) => id = to,
+                None => break,
+            }
+            if let DFANodeType::Terminal(rule) = self.nodes.get(&id).unwrap().nodetype {
+                longest = Some((i + ch.len_utf8(), rule));
+            }
+        }
+        longest
+    }
+
+    /// find the leftmost-longest match in `string` and return its byte range,
+    /// a match may be empty if the language contains the empty string
+    pub fn find(&self, string : &str) -> Option<Range<usize>> {
+        self.find_at(string, 0)
+    }
+
+    /// iterate the non-overlapping leftmost-longest matches in `string`
+    pub fn find_iter<'a>(&'a self, string : &'a str) -> Matches<'a> {
+        Matches {
+            dfa : self,
+            string,
+            pos : Some(0),
+        }
+    }
+
+    /// same as find, but only matches starting at byte `pos` or later
+    fn find_at(&self, string : &str, pos : usize) -> Option<Range<usize>> {
+        string[pos..].char_indices()
+            .map(|(i, _)| pos + i)
+            .chain(Some(string.len()))
+            .find_map(|start| {
+                self.accepts_prefix(&string[start..])
+                    .map(|len| start..start + len)
+            })
+    }
+
+    /// the node reached from `id` on `ch`, if there is an edge for it
+    fn step(&self, id : u64, ch : char) -> Option<u64> {
+        self.nodes.get(&id)
+            .expect("unknown id to step")
+            .edges
+            .iter()
+            .find(|(token, _)| token.matches(ch))
+            .map(|(_, to)| *to)
+    }
+
+    fn is_terminal(&self, id : u64) -> bool {
+        self.nodes.get(&id).unwrap().nodetype.is_terminal()
+    }
+
+    /// return the longest substring of `string` which is in the language, the
+    /// leftmost one if several are equally long. every start position is tried,
+    /// so a candidate overlapping an abandoned prefix is not missed
+    pub fn scanner(&self, string : &str) -> String {
+        let mut max_ans : Option<Range<usize>> = None;
+        let mut max_len = 0;
+        for (start, _) in string.char_indices() {
+            if let Some(len) = self.accepts_prefix(&string[start..]) {
+                let ans = start..start + len;
+                let ans_len = string[ans.clone()].chars().count();
+                if max_ans.is_none() || ans_len > max_len {
+                    max_ans = Some(ans);
+                    max_len = ans_len;
                 }
-            } else {
-                pos_flag = !pos_flag;
-            }
-            let token = Token::Character(ch);    
-            let mut flag = false;
-            if self.nodes.get(&id).unwrap().nodetype == DFANodeType::Terminal {
-                if max_ans.len() < ans.len() {
-                    max_ans = ans.clone(); 
+            }
+        }
+
+        max_ans
+            .map(|range| string[range].to_string())
+            .unwrap_or_default()
+    }
+
+    /// the chars on some edge of the DFA
+    pub fn alphabet(&self) -> CharClass {
+        CharClass::new(self.nodes.values().flat_map(|node| node.edges.iter().flat_map(|edge| edge.0.ranges())).collect())
+    }
+
+    /// make the DFA total over `alphabet`: the chars of `alphabet` a node has no edge
+    /// for go to a new non-terminal sink node, which loops on all of `alphabet`.
+    /// the sink is only added if some edge is missing
+    pub fn complete(&mut self, alphabet : &CharClass) {
+        let sink = self.nodes.keys().max().unwrap() + 1;
+        let mut ids : Vec<u64> = self.nodes.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            let node = self.nodes.get_mut(&id).unwrap();
+            // the chars of alphabet which are not covered, as alphabet and not covered
+            let mut ranges = alphabet.negate().ranges().to_vec();
+            ranges.extend(node.edges.iter().flat_map(|edge| edge.0.ranges()));
+            let missing = CharClass::new(ranges).negate();
+            if !missing.is_empty() {
+                node.push(Token::from_ranges(missing.ranges().to_vec()), sink);
+            }
+        }
+        if self.nodes.values().any(|node| node.edges.iter().any(|edge| edge.1 == sink)) {
+            let mut node = DFANode::new(sink, DFANodeType::NonTerminal);
+            node.push(Token::from_ranges(alphabet.ranges().to_vec()), sink);
+            self.nodes.insert(sink, node);
+            self.id_to_point.insert(sink, Vec::new());
+        }
+    }
+
+    /// remove the nodes which are unreachable from start or can't reach a terminal
+    /// node, and the edges to them. start is always kept
+    pub fn trim(&mut self) {
+        let useful = self.useful();
+        let start = self.start;
+        let keep = |id : &u64| *id == start || useful.contains(id);
+        self.nodes.retain(|id, _| keep(id));
+        self.id_to_point.retain(|id, _| keep(id));
+        for node in self.nodes.values_mut() {
+            node.edges.retain(|edge| keep(&edge.1));
+        }
+    }
+
+    /// the nodes which are reachable from start and can reach a terminal node
+    fn useful(&self) -> HashSet<u64> {
+        let mut reachable = HashSet::new();
+        let mut queue = VecDeque::new();
+        reachable.insert(self.start);
+        queue.push_back(self.start);
+        while let Some(id) = queue.pop_front() {
+            for (_, to) in &self.nodes.get(&id).unwrap().edges {
+                if reachable.insert(*to) {
+                    queue.push_back(*to);
                 }
             }
-            for (test_token, to) in self.nodes.get(&id)
-                .expect("unknown id to scanned")
-                .edges
-                .iter() 
-            {
-                //println!("edge is {:?} {}, current is {:?}", test_token, to, token);
-                if *test_token == token {
+        }
+
+        let mut incoming : HashMap<u64, Vec<u64>> = HashMap::new();
+        for node in self.nodes.values() {
+            for (_, to) in &node.edges {
+                incoming.entry(*to).or_default().push(node.nodeid);
+            }
+        }
+        let mut alive : HashSet<u64> = self.nodes.values()
+            .filter(|node| node.nodetype.is_terminal())
+            .map(|node| node.nodeid)
+            .collect();
+        let mut queue : VecDeque<u64> = alive.iter().cloned().collect();
+        while let Some(id) = queue.pop_front() {
+            for from in incoming.get(&id).into_iter().flatten() {
+                if alive.insert(*from) {
+                    queue.push_back(*from);
+                }
+            }
+        }
+
+        reachable.intersection(&alive).cloned().collect()
+    }
+
+    /// whether the DFA accepts no word at all
+    pub fn is_empty(&self) -> bool {
+        !self.useful().contains(&self.start)
+    }
+
+    /// whether the DFA accepts finitely many words, that is no cycle is both
+    /// reachable from start and able to reach a terminal node
+    pub fn is_finite(&self) -> bool {
+        self.finite_size().is_some()
+    }
+
+    /// whether the DFA accepts every word over `alphabet`, the empty word included
+    pub fn is_universal(&self, alphabet : &CharClass) -> bool {
+        let mut node = DFANode::new(0, DFANodeType::Terminal(0));
+        if !alphabet.is_empty() {
+            node.push(Token::from_ranges(alphabet.ranges().to_vec()), 0);
+        }
+        let mut universe = DFA {
+            start : 0,
+            nodes : HashMap::new(),
+            id_to_point : HashMap::new(),
+        };
+        universe.nodes.insert(0, node);
+        universe.id_to_point.insert(0, Vec::new());
+        universe.is_subset_of(self).is_ok()
+    }
+
+    /// the number of words the DFA accepts, None if it is infinite or the number
+    /// doesn't fit in u128, is_finite tells them apart. an edge counts once for
+    /// every char of its token
+    pub fn language_size(&self) -> Option<u128> {
+        self.finite_size().flatten()
+    }
+
+    /// the number of accepted words, None if the language is infinite and Some(None)
+    /// if it is finite but too large for u128
+    fn finite_size(&self) -> Option<Option<u128>> {
+        let useful = self.useful();
+        if !useful.contains(&self.start) {
+            return Some(Some(0));
+        }
+        // Kahn's algorithm from the nodes without useful edges backwards, a node
+        // is counted once all of its useful targets are, so the nodes left
+        // uncounted are on a useful cycle
+        let mut waiting : HashMap<u64, usize> = HashMap::new();
+        let mut incoming : HashMap<u64, Vec<u64>> = HashMap::new();
+        for id in &useful {
+            let targets = self.nodes.get(id).unwrap().edges
+                .iter()
+                .filter(|(_, to)| useful.contains(to));
+            waiting.insert(*id, targets.clone().count());
+            for (_, to) in targets {
+                incoming.entry(*to).or_default().push(*id);
+            }
+        }
+        let mut ready : Vec<u64> = waiting.iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(id, _)| *id)
+            .collect();
+        let mut sizes : HashMap<u64, Option<u128>> = HashMap::new();
+        while let Some(id) = ready.pop() {
+            let node = self.nodes.get(&id).unwrap();
+            let size = node.edges.iter()
+                .filter_map(|(token, to)| sizes.get(to).map(|size| size.and_then(|size| char_count(token).checked_mul(size))))
+                .try_fold(node.nodetype.is_terminal() as u128, |size, words| size.checked_add(words?));
+            sizes.insert(id, size);
+            for from in incoming.get(&id).into_iter().flatten() {
+                let count = waiting.get_mut(from).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(*from);
+                }
+            }
+        }
+        sizes.get(&self.start).cloned()
+    }
+
+    /// the number of accepted words of `len` chars, None if it doesn't fit in u128
+    pub fn count_words(&self, len : usize) -> Option<u128> {
+        *self.count_words_up_to(len).last().unwrap()
+    }
+
+    /// the number of accepted words of every length from 0 to `max_len`, the first
+    /// coefficients of the generating function of the language. an edge counts once
+    /// for every char of its token, and a count which doesn't fit in u128 is None
+    pub fn count_words_up_to(&self, max_len : usize) -> Vec<Option<u128>> {
+        // paths[id] is the number of words of the current length leading to id,
+        // None once it overflows. the counts only grow along the edges, so every
+        // count an overflowed number flows into overflows too
+        let mut paths : HashMap<u64, Option<u128>> = HashMap::new();
+        paths.insert(self.start, Some(1));
+        let mut counts = Vec::new();
+        for len in 0..=max_len {
+            counts.push(paths.iter()
+                .filter(|(id, _)| self.is_terminal(**id))
+                .try_fold(0u128, |count, (_, paths)| count.checked_add((*paths)?)));
+            if len == max_len {
+                break;
+            }
+            let mut next : HashMap<u64, Option<u128>> = HashMap::new();
+            for (id, paths) in &paths {
+                for (token, to) in &self.nodes.get(id).unwrap().edges {
+                    let entry = next.entry(*to).or_insert(Some(0));
+                    *entry = paths.and_then(|paths| (*entry)?.checked_add(char_count(token).checked_mul(paths)?));
+                }
+            }
+            paths = next;
+        }
+        counts
+    }
+
+    /// draw an accepted word of `len` chars uniformly at random, None if there is
+    /// none. the word is uniform as long as the counts don't saturate u128
+    pub fn sample<R : Rng>(&self, len : usize, rng : &mut R) -> Option<String> {
+        // suffixes[k][id] is the number of accepted words of k chars read from id
+        let mut suffixes : Vec<HashMap<u64, u128>> = Vec::new();
+        suffixes.push(self.nodes.keys().map(|id| (*id, self.is_terminal(*id) as u128)).collect());
+        for k in 1..=len {
+            let last = &suffixes[k - 1];
+            let counts = self.nodes.values()
+                .map(|node| {
+                    let count = node.edges.iter().fold(0u128, |count, (token, to)| {
+                        count.saturating_add(char_count(token).saturating_mul(*last.get(to).unwrap()))
+                    });
+                    (node.nodeid, count)
+                })
+                .collect();
+            suffixes.push(counts);
+        }
+
+        let mut id = self.start;
+        let mut word = String::new();
+        let count = *suffixes[len].get(&id).unwrap();
+        if count == 0 {
+            return None;
+        }
+        let mut pick = rng.gen_range(0..count);
+        for k in (1..=len).rev() {
+            // find the edge and the char the pick falls on, and pick again among
+            // the words after it
+            for (token, to) in &self.nodes.get(&id).unwrap().edges {
+                let words = *suffixes[k - 1].get(to).unwrap();
+                let weight = char_count(token).saturating_mul(words);
+                if pick < weight {
+                    word.push(nth_char(token, pick / words));
+                    pick %= words;
                     id = *to;
-                    flag = true;
                     break;
-                } 
+                }
+                pick -= weight;
             }
+        }
+        Some(word)
+    }
 
-            if !flag {
-                pos_flag = !pos_flag;
-                //println!("has been cleared, {}", pos_flag);
-                id = self.start;
-                ans.clear();
-            } else {
-                //println!("{} to {}", ch, ans);
-                ans.push(ch);
+    /// draw an accepted word of any length by a random walk from start, None if the
+    /// language is empty. the walk only takes edges to useful nodes and at a terminal
+    /// node stops with probability `stop`, or always if it can't go on.
+    /// also None if `stop` is not in [0, 1], or is 0 for an infinite language, where
+    /// the walk might never stop
+    pub fn sample_walk<R : Rng>(&self, stop : f64, rng : &mut R) -> Option<String> {
+        if !(0.0..=1.0).contains(&stop) || (stop == 0.0 && !self.is_finite()) {
+            return None;
+        }
+        let useful = self.useful();
+        if !useful.contains(&self.start) {
+            return None;
+        }
+        let mut id = self.start;
+        let mut word = String::new();
+        loop {
+            let edges : Vec<&(Token, u64)> = self.nodes.get(&id).unwrap().edges
+                .iter()
+                .filter(|(_, to)| useful.contains(to))
+                .collect();
+            if edges.is_empty() || (self.is_terminal(id) && rng.gen_bool(stop)) {
+                return Some(word);
             }
+            let (token, to) = edges[rng.gen_range(0..edges.len())];
+            word.push(nth_char(token, rng.gen_range(0..char_count(token))));
+            id = *to;
         }
+    }
 
-        max_ans
+
+    /// the DFA of every string which is not in the language, over all chars.
+    /// every terminal node of the result has rule 0
+    pub fn complement(&self) -> DFA {
+        self.product(self, |left, _| !left)
     }
 
-    pub fn simplifier(&mut self) {
-        let mut groups = Vec::new();
-        let mut map = BTreeMap::new();
-        let mut num = 1;
+    /// the DFA of the strings in both languages
+    pub fn intersect(&self, other : &DFA) -> DFA {
+        self.product(other, |left, right| left && right)
+    }
+
+    /// the DFA of the strings in either language
+    pub fn union(&self, other : &DFA) -> DFA {
+        self.product(other, |left, right| left || right)
+    }
+
+    /// the DFA of the strings in this language but not in `other`
+    pub fn difference(&self, other : &DFA) -> DFA {
+        self.product(other, |left, right| left && !right)
+    }
+
+    /// check that both DFAs accept the same language, or else return the shortest
+    /// word accepted by exactly one of them, the smallest one if there are several
+    pub fn equivalent(&self, other : &DFA) -> Result<(), String> {
+        match self.product(other, |left, right| left != right).shortest_word() {
+            Some(word) => Err(word),
+            None => Ok(()),
+        }
+    }
+
+    /// iterate the accepted words in shortlex order, shorter words first and words
+    /// of the same length in char order. the iterator ends if the language is finite
+    pub fn words_by_length(&self) -> Words<'_> {
+        Words::new(self)
+    }
+
+    /// check that every word this DFA accepts is accepted by `other`, or else
+    /// return the shortest word accepted only by this DFA
+    pub fn is_subset_of(&self, other : &DFA) -> Result<(), String> {
+        match self.difference(other).shortest_word() {
+            Some(word) => Err(word),
+            None => Ok(()),
+        }
+    }
+
+    /// breadth-first search for a terminal node, taking the edges in order of their
+    /// first char, so the word is the shortest and then the smallest accepted one
+    pub fn shortest_word(&self) -> Option<String> {
+        let mut parent : HashMap<u64, (u64, char)> = HashMap::new();
+        let mut queue = VecDeque::new();
+        let mut visited = HashSet::new();
+        visited.insert(self.start);
+        queue.push_back(self.start);
+        while let Some(id) = queue.pop_front() {
+            if self.is_terminal(id) {
+                let mut word = Vec::new();
+                let mut id = id;
+                while let Some((from, ch)) = parent.get(&id) {
+                    word.push(*ch);
+                    id = *from;
+                }
+                return Some(word.into_iter().rev().collect());
+            }
+            let mut edges : Vec<(char, u64)> = self.nodes.get(&id).unwrap().edges
+                .iter()
+                .filter_map(|(token, to)| token.ranges().first().map(|range| (range.0, *to)))
+                .collect();
+            edges.sort();
+            for (ch, to) in edges {
+                if visited.insert(to) {
+                    parent.insert(to, (id, ch));
+                    queue.push_back(to);
+                }
+            }
+        }
+        None
+    }
+
+    /// product construction over the pairs of reachable nodes, where a missing edge
+    /// goes to a dead node of its side. a pair is terminal if `accept` holds for
+    /// whether each side is terminal, with the rule id of this side if it is
+    /// terminal, or else of `other`. the pair of two dead nodes is only built if it
+    /// is terminal, then it loops on every char
+    fn product<F>(&self, other : &DFA, accept : F) -> DFA
+    where
+        F : Fn(bool, bool) -> bool
+    {
+        let nodetype = |pair : (Option<u64>, Option<u64>)| {
+            let left = pair.0.map(|id| self.nodes.get(&id).unwrap().nodetype.clone());
+            let right = pair.1.map(|id| other.nodes.get(&id).unwrap().nodetype.clone());
+            let left = left.unwrap_or(DFANodeType::NonTerminal);
+            let right = right.unwrap_or(DFANodeType::NonTerminal);
+            if !accept(left.is_terminal(), right.is_terminal()) {
+                return DFANodeType::NonTerminal;
+            }
+            match (left, right) {
+                (DFANodeType::Terminal(rule), _) | (_, DFANodeType::Terminal(rule)) => DFANodeType::Terminal(rule),
+                _ => DFANodeType::Terminal(0),
+            }
+        };
+        let dead = (None, None);
+        let dead_is_terminal = nodetype(dead).is_terminal();
+
+        // the dead pair also steps on the chars which are on no edge
+        let mut ranges = split_ranges(self.nodes.values()
+            .chain(other.nodes.values())
+            .flat_map(|node| node.edges.iter().map(|edge| &edge.0)));
+        if dead_is_terminal {
+            let mut all : Vec<(char, char)> = CharClass::new(ranges.clone()).negate().ranges().to_vec();
+            all.extend(ranges);
+            all.sort();
+            ranges = all;
+        }
+
+        let start = (Some(self.start), Some(other.start));
+        let mut pair_to_id = HashMap::new();
         let mut nodes = HashMap::new();
         let mut id_to_point = HashMap::new();
-        let mut start = 0;
-        groups.push(BTreeSet::new());
-        groups.push(BTreeSet::new());
-        self.nodes
-            .iter()
-            .for_each(|(key, value)| {
-                if value.nodetype == DFANodeType::Terminal {
-                    groups.get_mut(0).unwrap().insert(*key);
-                    map.insert(*key, 0); 
-                } else {
-                    groups.get_mut(1).unwrap().insert(*key);
-                    map.insert(*key, 1);
+        let mut queue = VecDeque::new();
+        pair_to_id.insert(start, 0);
+        queue.push_back(start);
+        while let Some(pair) = queue.pop_front() {
+            let id = *pair_to_id.get(&pair).unwrap();
+            let mut node = DFANode::new(id, nodetype(pair));
+            let mut targets : Vec<(u64, Vec<(char, char)>)> = Vec::new();
+            for (start, end) in &ranges {
+                let to = (
+                    pair.0.and_then(|id| self.step(id, *start)),
+                    pair.1.and_then(|id| other.step(id, *start)),
+                );
+                if to == dead && !dead_is_terminal {
+                    continue;
                 }
-            });
+                if !pair_to_id.contains_key(&to) {
+                    pair_to_id.insert(to, pair_to_id.len() as u64);
+                    queue.push_back(to);
+                }
+                let to = *pair_to_id.get(&to).unwrap();
+                match targets.iter_mut().find(|target| target.0 == to) {
+                    Some(target) => target.1.push((*start, *end)),
+                    None => targets.push((to, vec![(*start, *end)])),
+                }
+            }
+            for (to, target_ranges) in targets {
+                node.push(Token::from_ranges(target_ranges), to);
+            }
+            id_to_point.insert(id, Vec::new());
+            nodes.insert(id, node);
+        }
 
+        DFA {
+            start : 0,
+            nodes,
+            id_to_point,
+        }
+    }
 
-        let mut pre_size = 0;
-        loop {
-            let size = groups.len();
-            if size == pre_size {
-                break;
+    /// the NFA with the same points, edges and start, whose accepting points are the
+    /// terminal nodes with their rule ids
+    pub fn to_nfa(&self) -> NFA {
+        let mut nfa = NFA::with_start(self.start);
+        let mut ids : Vec<&u64> = self.nodes.keys().collect();
+        ids.sort();
+        for id in ids {
+            let node = self.nodes.get(id).unwrap();
+            if let DFANodeType::Terminal(rule) = node.nodetype {
+                nfa.add_accepting_rule(*id, rule);
             }
-            for i in 0..size {
-                let mut to_type : HashMap<Vec<(Token, u64)>, Vec<u64>> = HashMap::new();
-                //let first_element = top.pop_front().expect("the input file didn't have start and end");
-                //{
-                //    let mut tmp_vec = Vec::new();
-                //    tmp_vec.push(first_element);
-                //    let mut first_element_to_type : Vec<_> = self.nodes
-                //        .get(&first_element)
-                //        .unwrap()
-                //        .edges
-                //        .iter()
-                //        .map(|(token, id)| {
-                //            println!("id {} to {}, {:?}", first_element,  *map.get(id).unwrap(), token);
-                //            (token.clone(), *map.get(id).unwrap())
-                //        })
-                //        .collect();
-                //    first_element_to_type.sort();
-
-                //    to_type.insert(first_element_to_type, tmp_vec);
-                //}
-                
-                for other in groups.get_mut(i).unwrap().iter() {
-                    let mut other_to_type : Vec<_> = self.nodes
-                        .get(&other)
-                        .unwrap()
-                        .edges
-                        .iter()
-                        .map(|(token, id)| {
-                            println!("id {} to {}, {:?}", other,  *map.get(id).unwrap(), token);
-                            (token.clone(), *map.get(id).unwrap())
-                        })
-                        .collect();
-                    other_to_type.sort();
-                    if to_type.get(&other_to_type).is_none() {
-                        let mut tmp_vec = Vec::new();
-                        tmp_vec.push(*other);
-                        to_type.insert(other_to_type.clone(), tmp_vec);
+            for (token, to) in &node.edges {
+                nfa.push(*id, (token.clone(), *to));
+            }
+        }
+        nfa
+    }
+
+    /// convert the DFA into a regular expression by state elimination, None if it
+    /// accepts nothing, see NFA::to_regex
+    pub fn to_regex(&self) -> Option<Regex> {
+        self.to_nfa().to_regex()
+    }
+
+    /// return the minimal DFA of the same language by Brzozowski's algorithm,
+    /// determinizing the reversed automaton twice. the rule ids of the terminal
+    /// nodes are not kept, every terminal node gets rule 0
+    pub fn minimize_brzozowski(&self) -> DFA {
+        let reversed = DFA::from_nfa(&self.to_nfa().reverse());
+        DFA::from_nfa(&reversed.to_nfa().reverse())
+    }
+
+    /// minimize the DFA in place, see minimize
+    pub fn simplifier(&mut self) {
+        *self = self.minimize();
+    }
+
+    /// return the minimal DFA of the same language, built by Hopcroft's partition
+    /// refinement. unreachable and dead nodes are dropped, nodes are numbered in
+    /// breadth-first order from start and the edges to the same node are merged,
+    /// so two DFAs of the same language minimize to the same DFA.
+    /// terminal nodes of different rules are never merged
+    pub fn minimize(&self) -> DFA {
+        // number the reachable nodes, the last index is a sink for missing edges
+        let mut ids = Vec::new();
+        let mut index = HashMap::new();
+        let mut queue = VecDeque::new();
+        index.insert(self.start, 0);
+        ids.push(self.start);
+        queue.push_back(self.start);
+        while let Some(id) = queue.pop_front() {
+            for (_, to) in &self.nodes.get(&id).unwrap().edges {
+                if !index.contains_key(to) {
+                    index.insert(*to, ids.len());
+                    ids.push(*to);
+                    queue.push_back(*to);
+                }
+            }
+        }
+        let sink = ids.len();
+        let ranges = split_ranges(ids.iter().flat_map(|id| self.nodes.get(id).unwrap().edges.iter().map(|edge| &edge.0)));
+
+        // delta[node][range] is the node reached on the chars of `range`
+        let delta : Vec<Vec<usize>> = (0..=sink)
+            .map(|i| {
+                ranges.iter()
+                    .map(|(start, _)| {
+                        ids.get(i)
+                            .and_then(|id| self.step(*id, *start))
+                            .map(|to| *index.get(&to).unwrap())
+                            .unwrap_or(sink)
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut inverse = vec![vec![Vec::new(); sink + 1]; ranges.len()];
+        for (from, targets) in delta.iter().enumerate() {
+            for (range, to) in targets.iter().enumerate() {
+                inverse[range][*to].push(from);
+            }
+        }
+
+        let nodetype = |i : usize| match ids.get(i) {
+            Some(id) => self.nodes.get(id).unwrap().nodetype.clone(),
+            None => DFANodeType::NonTerminal,
+        };
+        let mut partition = Partition::new((0..=sink).map(nodetype).collect());
+        let mut in_work = HashSet::new();
+        let mut work = Vec::new();
+        for block in 0..partition.len() {
+            for range in 0..ranges.len() {
+                in_work.insert((block, range));
+                work.push((block, range));
+            }
+        }
+        while let Some((splitter, range)) = work.pop() {
+            in_work.remove(&(splitter, range));
+            let sources : Vec<usize> = partition.members(splitter)
+                .iter()
+                .flat_map(|to| inverse[range][*to].iter().cloned())
+                .collect();
+            for (block, new_block) in partition.split(&sources) {
+                for range in 0..ranges.len() {
+                    let pair = if in_work.contains(&(block, range))
+                        || partition.members(new_block).len() < partition.members(block).len()
+                    {
+                        (new_block, range)
                     } else {
-                        to_type.get_mut(&other_to_type).unwrap().push(*other);
+                        (block, range)
+                    };
+                    if in_work.insert(pair) {
+                        work.push(pair);
                     }
                 }
-                
-                let mut flag = true;
-                for (key, value) in &to_type {
-                    if flag {
-                        let gen = i;
-                        flag = false;
+            }
+        }
+
+        // rebuild from the blocks, skipping the dead block which contains the sink
+        let dead = partition.block_of(sink);
+        let mut block_to_id = HashMap::new();
+        let mut nodes = HashMap::new();
+        let mut id_to_point = HashMap::new();
+        let mut queue = VecDeque::new();
+        block_to_id.insert(partition.block_of(0), 0);
+        queue.push_back(partition.block_of(0));
+        while let Some(block) = queue.pop_front() {
+            let id = *block_to_id.get(&block).unwrap();
+            let first = partition.members(block)[0];
+            let mut node = DFANode::new(id, nodetype(first));
+            if block != dead {
+                let mut targets : Vec<(usize, Vec<(char, char)>)> = Vec::new();
+                for (range, to) in delta[first].iter().enumerate() {
+                    let to = partition.block_of(*to);
+                    if to == dead {
                         continue;
                     }
-                    groups.push(value.iter().cloned().collect());
-                    value.iter()
-                        .for_each(|item| {
-                            map.insert(*item, groups.len() as u64 - 1);                    
-                            groups.get_mut(i).unwrap().remove(item);
-                        });
+                    match targets.iter_mut().find(|target| target.0 == to) {
+                        Some(target) => target.1.push(ranges[range]),
+                        None => targets.push((to, vec![ranges[range]])),
+                    }
+                }
+                for (to, target_ranges) in targets {
+                    if !block_to_id.contains_key(&to) {
+                        block_to_id.insert(to, block_to_id.len() as u64);
+                        queue.push_back(to);
+                    }
+                    node.push(Token::from_ranges(target_ranges), *block_to_id.get(&to).unwrap());
                 }
-            }   
-            pre_size = size;
-        }
-
-        for i in 0..groups.len() { 
-            if groups.get(i).unwrap().len() == 0 {
-                continue;
             }
-            let mut nodetype = DFANodeType::NonTerminal;
-            let to_insert : Vec<_> = groups.get(i).unwrap()
+            let mut points : Vec<u64> = partition.members(block)
                 .iter()
-                .map(|item| {
-                    if self.nodes.get(item).unwrap().nodetype == DFANodeType::Terminal {
-                        nodetype = DFANodeType::Terminal;
-                    }
-                    if *item == self.start {
-                        start = i;
-                    }
-                    item
-                })
-                //.flat_map(|item| {
-                //    print!("{} ", item);
-                //    self.id_to_point.get(item).unwrap()
-                //})
-                .cloned()
+                .filter_map(|i| ids.get(*i).cloned())
                 .collect();
-            id_to_point.insert(i as u64, to_insert);
-            let mut node = DFANode::new(i as u64, nodetype);
-            self.nodes.get(groups.get(i).unwrap().iter().next().unwrap())
-                .unwrap()
-                .edges
-                .iter()
-                .for_each(|(token, to)| {
-                    node.push(token.clone(), map.get(to).unwrap().clone());
-                });
-            nodes.insert(i as u64, node);
+            points.sort();
+            id_to_point.insert(id, points);
+            nodes.insert(id, node);
         }
 
+        DFA {
+            start : 0,
+            nodes,
+            id_to_point,
+        }
+    }
 
+}
 
-        self.start = start as u64;
-        self.nodes = nodes;
-        self.id_to_point = id_to_point;
-        
-        
-        
+/// refinable partition of 0..n used by minimize. the members of every block are
+/// kept together in `elements`, so a block is split in time linear to the
+/// number of marked members
+struct Partition {
+    elements : Vec<usize>,
+    location : Vec<usize>,
+    block : Vec<usize>,
+    /// the range of every block in `elements`
+    bounds : Vec<(usize, usize)>,
+    /// how many members at the front of every block are marked
+    marked : Vec<usize>,
+}
+
+impl Partition {
+    /// one block for every distinct key
+    fn new<K : Eq + std::hash::Hash>(keys : Vec<K>) -> Self {
+        let mut key_to_block = HashMap::new();
+        let mut members : Vec<Vec<usize>> = Vec::new();
+        for (i, key) in keys.into_iter().enumerate() {
+            let block = *key_to_block.entry(key).or_insert_with(|| {
+                members.push(Vec::new());
+                members.len() - 1
+            });
+            members[block].push(i);
+        }
+
+        let n = members.iter().map(Vec::len).sum();
+        let mut partition = Partition {
+            elements : Vec::with_capacity(n),
+            location : vec![0; n],
+            block : vec![0; n],
+            bounds : Vec::new(),
+            marked : vec![0; members.len()],
+        };
+        for (block, members) in members.into_iter().enumerate() {
+            let start = partition.elements.len();
+            for i in members {
+                partition.location[i] = partition.elements.len();
+                partition.block[i] = block;
+                partition.elements.push(i);
+            }
+            partition.bounds.push((start, partition.elements.len()));
+        }
+        partition
+    }
+
+    fn len(&self) -> usize {
+        self.bounds.len()
     }
 
+    fn block_of(&self, i : usize) -> usize {
+        self.block[i]
+    }
+
+    fn members(&self, block : usize) -> &[usize] {
+        let (start, end) = self.bounds[block];
+        &self.elements[start..end]
+    }
+
+    /// split every block into its members in `marks` and the others, the marked
+    /// members get a new block. return the (old block, new block) pairs
+    fn split(&mut self, marks : &[usize]) -> Vec<(usize, usize)> {
+        let mut touched = Vec::new();
+        for i in marks {
+            let block = self.block[*i];
+            let (start, _) = self.bounds[block];
+            let front = start + self.marked[block];
+            if self.location[*i] < front {
+                continue;
+            }
+            if self.marked[block] == 0 {
+                touched.push(block);
+            }
+            let other = self.elements[front];
+            self.elements.swap(self.location[*i], front);
+            self.location[other] = self.location[*i];
+            self.location[*i] = front;
+            self.marked[block] += 1;
+        }
+
+        let mut splits = Vec::new();
+        for block in touched {
+            let (start, end) = self.bounds[block];
+            let marked = std::mem::replace(&mut self.marked[block], 0);
+            if marked == end - start {
+                continue;
+            }
+            let new_block = self.bounds.len();
+            self.bounds.push((start, start + marked));
+            self.bounds[block] = (start + marked, end);
+            self.marked.push(0);
+            for i in &self.elements[start..start + marked] {
+                self.block[*i] = new_block;
+            }
+            splits.push((block, new_block));
+        }
+        splits
+    }
 }
 
 type Nd = u64;
@@ -312,22 +907,212 @@ impl<'a> dot::Labeller<'a, Nd, Ed> for DFA {
         dot::Id::new(format!("node{}", *n)).unwrap()
     }
 
-    fn edge_label(&self, ed : &Ed) -> dot::LabelText {
-        let s = match ed.2 {
+    fn edge_label(&self, ed : &Ed) -> dot::LabelText<'_> {
+        let s = match &ed.2 {
             Token::Epsilon => "Epsilon".to_string(),
             Token::Character(ch) => ch.to_string(),
-            _ => unreachable!(),
+            Token::Identifier(name) => name.clone(),
+            Token::Class(class) => class.to_string(),
+        };
+        dot::LabelText::LabelStr(Cow::Owned(s))
+    }
+}
+
+/// how many chars an edge labelled with `token` can be taken on
+fn char_count(token : &Token) -> u128 {
+    token.ranges()
+        .iter()
+        .map(|(start, end)| (char_index(*end) - char_index(*start) + 1) as u128)
+        .sum()
+}
+
+/// the char at position `n` among the chars of `token`
+fn nth_char(token : &Token, mut n : u128) -> char {
+    for (start, end) in token.ranges() {
+        let chars = (char_index(end) - char_index(start) + 1) as u128;
+        if n < chars {
+            return index_char(char_index(start) + n as u32);
+        }
+        n -= chars;
+    }
+    panic!("char position out of the token");
+}
+
+/// iterator returned by DFA::find_iter
+pub struct Matches<'a> {
+    dfa : &'a DFA,
+    string : &'a str,
+    /// where the next search starts, None after the end of the string
+    pos : Option<usize>,
+}
+
+impl<'a> Iterator for Matches<'a> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let found = self.dfa.find_at(self.string, self.pos?)?;
+        // step over an empty match, or it would be found again
+        self.pos = if found.is_empty() {
+            self.string[found.end..].chars()
+                .next()
+                .map(|ch| found.end + ch.len_utf8())
+        } else {
+            Some(found.end)
         };
-        dot::LabelText::LabelStr(Cow::Owned(format!("{}", s)))
+        Some(found)
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// iterator returned by DFA::words_by_length. the words of every length are
+/// enumerated depth-first in char order, only stepping into nodes which accept a
+/// word of the remaining length, so every step leads to a word and a class like
+/// `.` is walked one char at a time instead of being expanded at once
+pub struct Words<'a> {
+    dfa : &'a DFA,
+    /// the sorted (start, end, to) edges of every useful node to useful nodes,
+    /// with the chars as char indexes
+    edges : HashMap<u64, Vec<(u32, u32, u64)>>,
+    /// the useful nodes with an edge to every useful node
+    incoming : HashMap<u64, Vec<u64>>,
+    /// alive[k] holds the nodes which accept a word of k chars
+    alive : Vec<HashSet<u64>>,
+    /// the length of the words being enumerated
+    len : usize,
+    /// no word is longer, if the language is finite
+    max_len : Option<usize>,
+    /// the node at every depth of the current path and the (edge, char index) to
+    /// try next from it, empty before the first word of `len`
+    stack : Vec<(u64, usize, u32)>,
+    word : String,
+    /// whether the words of `len` are still to be started
+    fresh : bool,
+}
+
+impl<'a> Words<'a> {
+    fn new(dfa : &'a DFA) -> Self {
+        let useful = dfa.useful();
+        let mut edges = HashMap::new();
+        let mut incoming : HashMap<u64, Vec<u64>> = HashMap::new();
+        for id in &useful {
+            let mut node_edges : Vec<(u32, u32, u64)> = dfa.nodes.get(id).unwrap().edges
+                .iter()
+                .filter(|(_, to)| useful.contains(to))
+                .flat_map(|(token, to)| token.ranges().into_iter().map(move |(start, end)| (char_index(start), char_index(end), *to)))
+                .collect();
+            node_edges.sort();
+            for (_, _, to) in &node_edges {
+                incoming.entry(*to).or_default().push(*id);
+            }
+            edges.insert(*id, node_edges);
+        }
+        let terminals = useful.iter().filter(|id| dfa.is_terminal(**id)).cloned().collect();
+        // a word of a finite language never visits a node twice
+        let max_len = if dfa.is_finite() { Some(useful.len()) } else { None };
+        Words {
+            dfa,
+            edges,
+            incoming,
+            alive : vec![terminals],
+            len : 0,
+            max_len,
+            stack : Vec::new(),
+            word : String::new(),
+            fresh : true,
+        }
+    }
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.fresh {
+                if self.max_len.is_some_and(|max_len| self.len > max_len) {
+                    return None;
+                }
+                while self.alive.len() <= self.len {
+                    let alive = self.alive.last().unwrap()
+                        .iter()
+                        .flat_map(|id| self.incoming.get(id).into_iter().flatten())
+                        .cloned()
+                        .collect();
+                    self.alive.push(alive);
+                }
+                self.fresh = false;
+                if self.alive[self.len].contains(&self.dfa.start) {
+                    let first = self.edges.get(&self.dfa.start).unwrap().first().map_or(0, |edge| edge.0);
+                    self.stack.push((self.dfa.start, 0, first));
+                }
+            }
+
+            let remaining = (self.len + 1).saturating_sub(self.stack.len());
+            let (id, edge, index) = match self.stack.last_mut() {
+                Some(frame) => frame,
+                None => {
+                    self.len += 1;
+                    self.fresh = true;
+                    continue;
+                }
+            };
+            if remaining == 0 {
+                let word = self.word.clone();
+                self.stack.pop();
+                self.word.pop();
+                return Some(word);
+            }
+
+            // the next char from this node leading to a word of the remaining length
+            let edges = self.edges.get(id).unwrap();
+            let mut next = None;
+            while let Some((_, end, to)) = edges.get(*edge) {
+                if *index <= *end && self.alive[remaining - 1].contains(to) {
+                    next = Some((*index, *to));
+                    *index += 1;
+                    break;
+                }
+                *edge += 1;
+                *index = edges.get(*edge).map_or(0, |edge| edge.0);
+            }
+            match next {
+                Some((index, to)) => {
+                    self.word.push(index_char(index));
+                    let first = self.edges.get(&to).unwrap().first().map_or(0, |edge| edge.0);
+                    self.stack.push((to, 0, first));
+                }
+                None => {
+                    self.stack.pop();
+                    self.word.pop();
+                }
+            }
+        }
+    }
+}
+
+/// a terminal node carries the rule id of the accepting NFA points it contains,
+/// automata which are not built by a Lexer only use rule 0
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub enum DFANodeType {
-    Terminal,
+    Terminal(usize),
     NonTerminal,
 }
 
+impl DFANodeType {
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, DFANodeType::Terminal(_))
+    }
+
+    /// the type of the DFA node made of NFA `points`. if several accepting points
+    /// are in it, the smallest rule id wins
+    fn of_points(nfa : &NFA, points : &[u64]) -> Self {
+        points.iter()
+            .filter_map(|id| nfa.get_rule(*id))
+            .min()
+            .map(DFANodeType::Terminal)
+            .unwrap_or(DFANodeType::NonTerminal)
+    }
+}
+
 struct DFANode {
     nodeid : u64,
     nodetype : DFANodeType,
@@ -353,7 +1138,7 @@ impl DFANode {
 
 #[test]
 fn test_nfa_to_dfa_simplifier_1() {
-    let mut nfa = test_base_nfa_1();
+    let nfa = test_base_nfa_1();
     let mut dfa = DFA::from_nfa(&nfa);
     use std::fs::File;
     let mut output = File::create("simplifier1.dot").unwrap();
@@ -365,7 +1150,7 @@ fn test_nfa_to_dfa_simplifier_1() {
 
 #[test]
 fn test_nfa_to_dfa_simplifier_2() {
-    let mut nfa = test_base_nfa_2();
+    let nfa = test_base_nfa_2();
     let mut dfa = DFA::from_nfa(&nfa);
     use std::fs::File;
     let mut output = File::create("simplifier2.dot").unwrap();
@@ -379,8 +1164,8 @@ fn test_nfa_to_dfa_simplifier_2() {
 fn test_draw_dfa_automachine() {
     use std::fs::File;
     let mut output = File::create("example.dot").unwrap();
-    let mut nfa = test_base_nfa_1();
-    let mut dfa = DFA::from_nfa(&nfa);
+    let nfa = test_base_nfa_1();
+    let dfa = DFA::from_nfa(&nfa);
     dot::render(&dfa, &mut output).unwrap();
 }
 
@@ -388,26 +1173,27 @@ fn test_draw_dfa_automachine() {
 fn test_draw_dfa_automachine_2() {
     use std::fs::File;
     let mut output = File::create("example2.dot").unwrap();
-    let mut nfa = test_base_nfa_2();
-    let mut dfa = DFA::from_nfa(&nfa);
+    let nfa = test_base_nfa_2();
+    let dfa = DFA::from_nfa(&nfa);
     dot::render(&dfa, &mut output).unwrap();
 }
 
 #[test]
 fn test_nfa_to_dfa() {
-    let mut nfa = test_base_nfa_1();
+    let nfa = test_base_nfa_1();
     let dfa = DFA::from_nfa(&nfa);
     dfa.print();
 }
 
 #[test]
 fn test_nfa_to_dfa_2() {
-    let mut nfa = test_base_nfa_2(); 
+    let nfa = test_base_nfa_2(); 
     let dfa = DFA::from_nfa(&nfa);
     dfa.print();
 }
 
-fn test_base_nfa_2() -> NFA{
+#[cfg(test)]
+fn test_base_nfa_2() -> NFA {
     let mut nfa = NFA::new(0, 6);
     nfa.push(0, (Token::Epsilon, 5));
     nfa.push(5, (Token::Epsilon, 1));
@@ -424,10 +1210,9 @@ fn test_base_nfa_2() -> NFA{
     nfa
 }
 
+#[cfg(test)]
 fn test_base_nfa_1() -> NFA {
     let mut nfa = NFA::new(0, 9);
-    use std::fs::File;
-    let mut output = File::create("example2.dot").unwrap();
     nfa.push(0, (Token::Character('a'), 1));
     nfa.push(1, (Token::Epsilon, 2));
     nfa.push(2, (Token::Epsilon, 3));
@@ -443,3 +1228,535 @@ fn test_base_nfa_1() -> NFA {
     nfa.push(9, (Token::Epsilon, 9));
     nfa
 }
+
+#[test]
+fn test_regex_to_nfa() {
+    use dot::GraphWalk;
+    let edges = |nfa : &NFA| {
+        let mut edges : Vec<_> = nfa.edges()
+            .iter()
+            .filter(|edge| edge.0 != edge.1)
+            .cloned()
+            .collect();
+        edges.sort();
+        edges
+    };
+    let expect = test_base_nfa_1();
+    let nfa = NFA::from_regex("a(b|c)*").unwrap();
+    assert_eq!(edges(&nfa), edges(&expect));
+    assert_eq!((nfa.get_start(), nfa.get_accepting()), (expect.get_start(), expect.get_accepting()));
+}
+
+#[test]
+fn test_char_class_dfa() {
+    let dfa = DFA::from_nfa(&NFA::from_regex("[a-z]+|m[0-9]").unwrap());
+    for node in dfa.nodes.values() {
+        let mut ranges : Vec<_> = node.edges.iter().flat_map(|edge| edge.0.ranges()).collect();
+        ranges.sort();
+        assert_eq!(split_ranges(node.edges.iter().map(|edge| &edge.0)), ranges);
+    }
+    assert_eq!(dfa.nodes.get(&dfa.start).unwrap().edges.len(), 3);
+    assert!(dfa.accepts("abc") && dfa.accepts("m5") && dfa.accepts("m") && !dfa.accepts("a5"));
+
+    let mut dfa = DFA::from_nfa(&NFA::from_regex("\"[^\"]*\"").unwrap());
+    dfa.simplifier();
+    assert!(dfa.accepts("\"hi 中\"") && !dfa.accepts("\"a\"b\""));
+
+    let dfa = DFA::from_nfa(&NFA::from_regex("[一-龥]+").unwrap());
+    assert_eq!(dfa.nodes.len(), 2);
+    assert!(dfa.accepts("中文") && !dfa.accepts("中a"));
+}
+
+#[test]
+fn test_multiple_accepting() {
+    // `ab` or `ac`, ending in two different accepting points
+    let mut nfa = NFA::new(0, 2);
+    nfa.push(0, (Token::Character('a'), 1));
+    nfa.push(1, (Token::Character('b'), 2));
+    nfa.push(1, (Token::Character('c'), 3));
+    nfa.add_accepting(3);
+    assert!(nfa.is_accepting(2) && nfa.is_accepting(3) && !nfa.is_accepting(1));
+
+    let dfa = DFA::from_nfa(&nfa);
+    assert!(dfa.accepts("ab") && dfa.accepts("ac") && !dfa.accepts("a"));
+
+    let mut output = Vec::new();
+    dot::render(&nfa, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.matches("doublecircle").count(), 2);
+}
+
+/// every string over `alphabet` up to `len` chars, shortest first
+#[cfg(test)]
+fn all_words(alphabet : &str, len : usize) -> Vec<String> {
+    let mut words = vec![String::new()];
+    let mut last = vec![String::new()];
+    for _ in 0..len {
+        last = last.iter()
+            .flat_map(|word| alphabet.chars().map(move |ch| format!("{}{}", word, ch)))
+            .collect();
+        words.extend(last.iter().cloned());
+    }
+    words
+}
+
+#[test]
+fn test_nfa_simulation() {
+    for pattern in &["a(b|c)*", "(a|b)*(aa|bb)(a|b)*", "ab|b*", "(ab)+c?", "[^a]b*", "ε"] {
+        let nfa = NFA::from_regex(pattern).unwrap();
+        let dfa = DFA::from_nfa(&nfa);
+        for word in all_words("abc", 5) {
+            assert_eq!(nfa.is_match(&word), dfa.accepts(&word), "{} on {}", pattern, word);
+
+            // the leftmost start with a match, and the longest match from there
+            let expect = (0..=word.len())
+                .filter_map(|start| {
+                    (start..=word.len())
+                        .rev()
+                        .find(|end| dfa.accepts(&word[start..*end]))
+                        .map(|end| start..end)
+                })
+                .next();
+            assert_eq!(nfa.find(&word), expect, "{} on {}", pattern, word);
+        }
+    }
+    let nfa = NFA::from_regex("[一-龥]+").unwrap();
+    assert_eq!(nfa.find("ab中文!"), Some(2..8));
+}
+
+#[test]
+fn test_accepts() {
+    use crate::formal::FormalLanguage;
+    use std::io::BufReader;
+    use std::fs::File;
+    let data = FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test2.in").unwrap())).unwrap();
+    let formal = FormalLanguage::new(Token::Identifier("S".to_string()), data).unwrap();
+    let mut dfa = DFA::from_nfa(&formal.get_nfa());
+    dfa.simplifier();
+    for word in &["", "bc", "aabca", "bcaaa"] {
+        assert!(dfa.accepts(word), "{}", word);
+    }
+    for word in &["a", "b", "cb", "abcb", "xbc", "aaaabcaab"] {
+        assert!(!dfa.accepts(word), "{}", word);
+    }
+
+    assert_eq!(dfa.accepts_prefix("aaaabcaab"), Some(8));
+    assert_eq!(dfa.accepts_prefix("abx"), Some(0));
+    let dfa = DFA::from_nfa(&NFA::from_regex("a+").unwrap());
+    assert_eq!(dfa.accepts_prefix("baa"), None);
+    assert_eq!(dfa.accepts_prefix("aab"), Some(2));
+}
+
+#[test]
+fn test_find() {
+    for pattern in &["a(b|c)*", "ab|b*", "(ab)+c?", "[^a]b*", "ε"] {
+        let nfa = NFA::from_regex(pattern).unwrap();
+        let dfa = DFA::from_nfa(&nfa);
+        for word in all_words("abc", 5) {
+            assert_eq!(dfa.find(&word), nfa.find(&word), "{} on {}", pattern, word);
+        }
+    }
+
+    let dfa = DFA::from_nfa(&NFA::from_regex("[a-z]+|[0-9]+").unwrap());
+    let matches : Vec<_> = dfa.find_iter("let x1 = 42;").collect();
+    assert_eq!(matches, vec![0..3, 4..5, 5..6, 9..11]);
+    let dfa = DFA::from_nfa(&NFA::from_regex("a*").unwrap());
+    let matches : Vec<_> = dfa.find_iter("baaé").collect();
+    assert_eq!(matches, vec![0..0, 1..3, 3..3, 5..5]);
+}
+
+#[test]
+fn test_scanner() {
+    let dfa = DFA::from_nfa(&NFA::from_regex("ab").unwrap());
+    assert_eq!(dfa.scanner("aab"), "ab");
+    assert_eq!(dfa.scanner("xyz"), "");
+
+    // compare with checking every substring, on random strings
+    let mut seed : u64 = 12345;
+    let mut random = |n : u64| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) % n
+    };
+    for pattern in &["ab", "a(b|c)*", "(a|b)*(aa|bb)(a|b)*", "ab|b*", "(ab)+c?", "[^a]b*", "aab|abc"] {
+        let mut dfa = DFA::from_nfa(&NFA::from_regex(pattern).unwrap());
+        dfa.simplifier();
+        for _ in 0..200 {
+            let len = random(12);
+            let word : String = (0..len).map(|_| ['a', 'b', 'c', 'd'][random(4) as usize]).collect();
+            let mut expect = String::new();
+            for start in 0..word.len() {
+                for end in start..=word.len() {
+                    if dfa.accepts(&word[start..end]) && end - start > expect.len() {
+                        expect = word[start..end].to_string();
+                    }
+                }
+            }
+            assert_eq!(dfa.scanner(&word), expect, "{} on {}", pattern, word);
+        }
+    }
+}
+
+#[cfg(test)]
+type DFAShape = (u64, Vec<(u64, DFANodeType, Vec<(Token, u64)>)>);
+
+/// the nodes of `dfa` in id order, to compare DFAs numbered the same way
+#[cfg(test)]
+fn dfa_shape(dfa : &DFA) -> DFAShape {
+    let mut nodes : Vec<_> = dfa.nodes.values()
+        .map(|node| (node.nodeid, node.nodetype.clone(), node.edges.clone()))
+        .collect();
+    nodes.sort_by_key(|node| node.0);
+    (dfa.start, nodes)
+}
+
+#[test]
+fn test_minimize() {
+    let patterns = [
+        "a(b|c)*", "(a|b)*(aa|bb)(a|b)*", "ab|b*", "(ab)+c?", "[^a]b*", "ε",
+        "(a|b)*a(a|b)(a|b)", "[a-c]*b|a[b-d]", "(aa|a)*", "a*b*a*b*",
+    ];
+    for pattern in &patterns {
+        let dfa = DFA::from_nfa(&NFA::from_regex(pattern).unwrap());
+        let minimal = dfa.minimize();
+        let brzozowski = dfa.minimize_brzozowski();
+        assert_eq!(minimal.nodes.len(), brzozowski.nodes.len(), "{}", pattern);
+        assert_eq!(dfa_shape(&minimal), dfa_shape(&brzozowski.minimize()), "{}", pattern);
+        assert_eq!(dfa_shape(&minimal), dfa_shape(&minimal.minimize()), "{}", pattern);
+        for word in all_words("abcd", 5) {
+            assert_eq!(minimal.accepts(&word), dfa.accepts(&word), "{} on {}", pattern, word);
+        }
+    }
+
+    // a b* and a b*|a b b* are the same language
+    let left = DFA::from_nfa(&NFA::from_regex("ab*").unwrap()).minimize();
+    let right = DFA::from_nfa(&NFA::from_regex("ab*|abb*").unwrap()).minimize();
+    assert_eq!(dfa_shape(&left), dfa_shape(&right));
+    assert_eq!(left.nodes.len(), 2);
+
+    // (a|b)*(aa|bb)(a|b)* needs 4 nodes, and the empty language keeps one
+    let dfa = DFA::from_nfa(&test_base_nfa_2()).minimize();
+    assert_eq!(dfa.nodes.len(), 4);
+    let mut nfa = NFA::new(0, 2);
+    nfa.push(0, (Token::Character('a'), 1));
+    let dfa = DFA::from_nfa(&nfa).minimize();
+    assert_eq!(dfa_shape(&dfa), (0, vec![(0, DFANodeType::NonTerminal, vec![])]));
+}
+
+#[test]
+fn test_reverse() {
+    let nfa = NFA::from_regex("ab*c|d").unwrap();
+    let reversed = nfa.reverse();
+    assert!(reversed.is_match("cba") && reversed.is_match("cbbba") && reversed.is_match("d"));
+    assert!(reversed.is_match("ca") && !reversed.is_match("abc") && !reversed.is_match("dd"));
+
+    // the suffixes of words ending in `ing` are found by running the reversed DFA
+    let dfa = DFA::from_nfa(&NFA::from_regex("[a-z]*ing").unwrap());
+    let reversed = DFA::from_nfa(&dfa.to_nfa().reverse());
+    assert!(reversed.accepts("gnirts") && !reversed.accepts("string"));
+    assert_eq!(reversed.accepts_prefix("gnirts"), Some(6));
+
+    // several accepting points become several start points
+    let dfa = DFA::from_nfa(&NFA::from_regex("a|bc|ε").unwrap());
+    let nfa = dfa.to_nfa();
+    assert_eq!(nfa.get_accepting().len(), 3);
+    assert_eq!(nfa.reverse().get_starts().len(), 3);
+    assert_eq!(dfa_shape(&dfa.minimize_brzozowski().minimize()), dfa_shape(&dfa.minimize()));
+}
+
+#[test]
+fn test_complete_and_trim() {
+    let mut dfa = DFA::from_nfa(&NFA::from_regex("a[b-d]*|e").unwrap());
+    let alphabet = dfa.alphabet();
+    assert_eq!(alphabet.ranges(), &[('a', 'e')]);
+    let states = dfa.nodes.len();
+
+    dfa.complete(&alphabet);
+    assert_eq!(dfa.nodes.len(), states + 1);
+    for node in dfa.nodes.values() {
+        for ch in "abcde".chars() {
+            assert!(dfa.step(node.nodeid, ch).is_some());
+        }
+        assert!(dfa.step(node.nodeid, 'f').is_none());
+    }
+    for word in all_words("abcdef", 3) {
+        assert_eq!(dfa.accepts(&word), NFA::from_regex("a[b-d]*|e").unwrap().is_match(&word), "{}", word);
+    }
+    // completing a total DFA adds nothing
+    dfa.complete(&alphabet);
+    assert_eq!(dfa.nodes.len(), states + 1);
+
+    dfa.trim();
+    assert_eq!(dfa.nodes.len(), states);
+    assert!(dfa.accepts("abdc") && dfa.accepts("e") && !dfa.accepts("ea"));
+
+    // the start is kept even if nothing is accepted
+    let mut dfa = DFA::from_nfa(&NFA::from_regex("a[^a]").unwrap());
+    dfa.complete(&CharClass::any());
+    dfa.nodes.values_mut().for_each(|node| node.nodetype = DFANodeType::NonTerminal);
+    dfa.trim();
+    assert_eq!(dfa.nodes.len(), 1);
+    assert!(dfa.nodes.get(&dfa.start).unwrap().edges.is_empty());
+}
+
+#[test]
+fn test_boolean_operations() {
+    let keyword = DFA::from_nfa(&NFA::from_regex("if|else|while").unwrap());
+    let ident = DFA::from_nfa(&NFA::from_regex("[a-z][a-z]*").unwrap());
+    let short = DFA::from_nfa(&NFA::from_regex("[a-z]?[a-z]?[a-z]?").unwrap());
+
+    let mut names = ident.difference(&keyword);
+    assert!(names.accepts("iff") && names.accepts("i") && !names.accepts("if") && !names.accepts("while"));
+    names.simplifier();
+    assert!(names.accepts("els") && !names.accepts("else") && !names.accepts(""));
+
+    let both = keyword.intersect(&short);
+    assert!(both.accepts("if") && !both.accepts("else") && !both.accepts("i"));
+    let either = keyword.union(&short);
+    assert!(either.accepts("while") && either.accepts("abc") && either.accepts("") && !either.accepts("abcd"));
+
+    let complement = short.complement();
+    assert!(complement.accepts("abcd") && complement.accepts("A") && complement.accepts("a1"));
+    assert!(!complement.accepts("") && !complement.accepts("abc"));
+    assert_eq!(dfa_shape(&complement.complement().minimize()), dfa_shape(&short.minimize()));
+
+    let words = all_words("abei", 3);
+    let left = DFA::from_nfa(&NFA::from_regex("(ab|e)*").unwrap());
+    let right = DFA::from_nfa(&NFA::from_regex("a*b|ei*").unwrap());
+    for word in words {
+        let (l, r) = (left.accepts(&word), right.accepts(&word));
+        assert_eq!(left.intersect(&right).accepts(&word), l && r, "{}", word);
+        assert_eq!(left.union(&right).accepts(&word), l || r, "{}", word);
+        assert_eq!(left.difference(&right).accepts(&word), l && !r, "{}", word);
+        assert_eq!(left.complement().accepts(&word), !l, "{}", word);
+    }
+
+    let mut output = Vec::new();
+    dot::render(&names, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with("digraph NFA"));
+    assert!(names.nodes.keys().all(|id| output.contains(&format!("node{}", id))));
+}
+
+#[test]
+fn test_equivalent() {
+    let left = DFA::from_nfa(&NFA::from_regex("(a|b)*abb").unwrap());
+    let right = DFA::from_nfa(&NFA::from_regex("(a|b)*a(b|a)b").unwrap());
+    assert_eq!(left.equivalent(&left.minimize()), Ok(()));
+    assert_eq!(left.equivalent(&right), Err("aab".to_string()));
+    assert_eq!(right.equivalent(&left), Err("aab".to_string()));
+
+    let star = DFA::from_nfa(&NFA::from_regex("(a*b*)*").unwrap());
+    let any = DFA::from_nfa(&NFA::from_regex("(a|b)*").unwrap());
+    assert_eq!(star.equivalent(&any), Ok(()));
+    let plus = DFA::from_nfa(&NFA::from_regex("(a|b)+").unwrap());
+    assert_eq!(star.equivalent(&plus), Err(String::new()));
+    let class = DFA::from_nfa(&NFA::from_regex("[a-c]x").unwrap());
+    let alternate = DFA::from_nfa(&NFA::from_regex("ax|bx|cx|dx").unwrap());
+    assert_eq!(class.equivalent(&alternate), Err("dx".to_string()));
+}
+
+#[test]
+fn test_is_subset_of() {
+    let keyword = DFA::from_nfa(&NFA::from_regex("if|else|while").unwrap());
+    let ident = DFA::from_nfa(&NFA::from_regex("[a-z_][a-z0-9_]*").unwrap());
+    assert_eq!(keyword.is_subset_of(&ident), Ok(()));
+    assert_eq!(ident.is_subset_of(&keyword), Err("_".to_string()));
+    assert_eq!(ident.is_subset_of(&ident), Ok(()));
+
+    let hex = DFA::from_nfa(&NFA::from_regex("0x[0-9a-fA-F]+").unwrap());
+    let number = DFA::from_nfa(&NFA::from_regex("[0-9]+|0x[0-9a-f]+").unwrap());
+    assert_eq!(hex.is_subset_of(&number), Err("0xA".to_string()));
+    let empty = DFA::from_nfa(&NFA::from_regex("a").unwrap()).intersect(&DFA::from_nfa(&NFA::from_regex("b").unwrap()));
+    assert_eq!(empty.is_subset_of(&keyword), Ok(()));
+    assert_eq!(number.is_subset_of(&empty), Err("0".to_string()));
+}
+
+#[test]
+fn test_language_queries() {
+    let dfa = |pattern| DFA::from_nfa(&NFA::from_regex(pattern).unwrap());
+    let empty = dfa("ab").intersect(&dfa("ba"));
+    assert!(empty.is_empty() && empty.is_finite());
+    assert_eq!(empty.language_size(), Some(0));
+    assert!(!dfa("ε").is_empty());
+    assert_eq!(dfa("ε").language_size(), Some(1));
+
+    assert_eq!(dfa("if|else|while").language_size(), Some(3));
+    assert_eq!(dfa("[a-c][0-9]?").language_size(), Some(33));
+    assert_eq!(dfa("(a|b)(a|b)(a|b)|aaa").language_size(), Some(8));
+    assert!(!dfa("ab*c").is_finite());
+    assert_eq!(dfa("ab*c").language_size(), None);
+    // a cycle which can't reach a terminal node keeps the language finite
+    let mut dead_loop = dfa("ab|c");
+    dead_loop.complete(&CharClass::new(vec![('a', 'c')]));
+    assert!(dead_loop.is_finite());
+    assert_eq!(dead_loop.language_size(), Some(2));
+    // long chains are counted without recursion
+    let mut nfa = NFA::new(0, 5000);
+    (0..5000).for_each(|id| nfa.push(id, (Token::Character('a'), id + 1)));
+    let chain = DFA::from_nfa(&nfa);
+    assert!(chain.is_finite());
+    assert_eq!(chain.language_size(), Some(1));
+
+    let alphabet = CharClass::new(vec![('a', 'b')]);
+    assert!(dfa("(a|b)*").is_universal(&alphabet));
+    assert!(dfa("(a*b*)*").is_universal(&alphabet));
+    assert!(!dfa("(a|b)+").is_universal(&alphabet));
+    assert!(!dfa("(a|b)*").is_universal(&CharClass::new(vec![('a', 'c')])));
+    assert!(dfa("ab").complement().is_universal(&CharClass::new(vec![('c', 'z')])));
+    assert!(dfa("ε").is_universal(&CharClass::new(Vec::new())));
+}
+
+#[test]
+fn test_words_by_length() {
+    let dfa = |pattern| DFA::from_nfa(&NFA::from_regex(pattern).unwrap());
+    assert_eq!(dfa("(a|b)*abb").shortest_word(), Some("abb".to_string()));
+    assert_eq!(dfa("ab").intersect(&dfa("b")).shortest_word(), None);
+
+    let words : Vec<String> = dfa("if|else|[x-z][0-1]?").words_by_length().collect();
+    assert_eq!(words, vec!["x", "y", "z", "if", "x0", "x1", "y0", "y1", "z0", "z1", "else"]);
+    let words : Vec<String> = dfa("(ab)*|c").words_by_length().take(4).collect();
+    assert_eq!(words, vec!["", "c", "ab", "abab"]);
+    assert_eq!(dfa("ab").intersect(&dfa("b")).words_by_length().next(), None);
+
+    // the words come out in the same order after minimization
+    let nfa = NFA::from_regex("(a|ba)*b?").unwrap();
+    let words : Vec<String> = DFA::from_nfa(&nfa).words_by_length().take(50).collect();
+    assert_eq!(words, DFA::from_nfa(&nfa).minimize().words_by_length().take(50).collect::<Vec<_>>());
+    assert!(words.iter().all(|word| nfa.is_match(word)));
+    assert!(words.windows(2).all(|pair| (pair[0].len(), &pair[0]) < (pair[1].len(), &pair[1])));
+
+    // a class is expanded lazily
+    let any : Vec<String> = dfa(".").words_by_length().skip(0xF800).take(2).collect();
+    assert_eq!(any, vec!["\u{10000}", "\u{10001}"]);
+    let wide = dfa(".x|ab");
+    let mut words = wide.words_by_length();
+    assert_eq!(words.next(), Some("\u{0}x".to_string()));
+    let words : Vec<String> = dfa("(a|b)(aa)*").words_by_length().take(4).collect();
+    assert_eq!(words, vec!["a", "b", "aaa", "baa"]);
+
+    // the words of a grammar
+    use crate::formal::FormalLanguage;
+    use std::io::BufReader;
+    use std::fs::File;
+    let data = FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test2.in").unwrap())).unwrap();
+    let formal = FormalLanguage::new(Token::Identifier("S".to_string()), data).unwrap();
+    let mut dfa = DFA::from_nfa(&formal.get_nfa());
+    dfa.simplifier();
+    assert_eq!(dfa.shortest_word(), Some(String::new()));
+    let words : Vec<String> = dfa.words_by_length().take(7).collect();
+    assert_eq!(words, vec!["", "bc", "abc", "bca", "aabc", "abca", "bcaa"]);
+}
+
+#[test]
+fn test_count_words() {
+    let nfa = NFA::from_regex("[a-z_][a-z0-9_]*").unwrap();
+    let dfa = DFA::from_nfa(&nfa);
+    assert_eq!(dfa.count_words(0), Some(0));
+    assert_eq!(dfa.count_words(1), Some(27));
+    assert_eq!(dfa.count_words(3), Some(27 * 37 * 37));
+    assert_eq!(dfa.count_words_up_to(3), dfa.minimize().count_words_up_to(3));
+    assert_eq!(DFA::from_nfa(&NFA::from_regex("if|else|while|for").unwrap()).count_words_up_to(5), vec![Some(0), Some(0), Some(1), Some(1), Some(1), Some(1)]);
+
+    // the counts agree with enumerating the words, before and after minimization
+    let nfa = NFA::from_regex("(a|ba)*b?|c(a|b|c)").unwrap();
+    let words = all_words("abc", 5);
+    for dfa in [DFA::from_nfa(&nfa), DFA::from_nfa(&nfa).minimize()] {
+        let counts = dfa.count_words_up_to(5);
+        for (len, count) in counts.iter().enumerate() {
+            let words = words.iter().filter(|word| word.len() == len && nfa.is_match(word)).count();
+            assert_eq!(*count, Some(words as u128), "{}", len);
+        }
+    }
+
+    // a finite language of size n has counts summing to n
+    let dfa = DFA::from_nfa(&NFA::from_regex("[0-9][0-9]?-[a-f]").unwrap());
+    assert_eq!(dfa.count_words_up_to(4).iter().map(|count| count.unwrap()).sum::<u128>(), dfa.language_size().unwrap());
+
+    // counts which don't fit in u128 are None instead of a wrong number
+    let huge = DFA::from_nfa(&NFA::from_regex("..........").unwrap());
+    let counts = huge.count_words_up_to(10);
+    assert_eq!(counts[5], Some(0));
+    assert_eq!(counts[10], None);
+    assert!(huge.is_finite());
+    assert_eq!(huge.language_size(), None);
+    let wide = DFA::from_nfa(&NFA::from_regex(".....").unwrap());
+    assert_eq!(wide.language_size(), Some((0x110000u128 - 0x800).pow(5)));
+}
+
+#[test]
+fn test_sample() {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    let nfa = NFA::from_regex("(a|ba)*b?|c[a-c]").unwrap();
+    let dfa = DFA::from_nfa(&nfa);
+    let mut rng = StdRng::seed_from_u64(7);
+
+    // every word of 4 chars is drawn about equally often
+    let mut seen : HashMap<String, usize> = HashMap::new();
+    for _ in 0..2000 {
+        let word = dfa.sample(4, &mut rng).unwrap();
+        assert!(word.chars().count() == 4 && nfa.is_match(&word), "{}", word);
+        *seen.entry(word).or_default() += 1;
+    }
+    assert_eq!(Some(seen.len() as u128), dfa.count_words(4));
+    assert!(seen.values().all(|count| *count > 2000 / seen.len() / 2), "{:?}", seen);
+
+    assert_eq!(dfa.sample(0, &mut rng), Some(String::new()));
+    assert_eq!(DFA::from_nfa(&NFA::from_regex("ab").unwrap()).sample(3, &mut rng), None);
+    let word = DFA::from_nfa(&NFA::from_regex("[\u{0}-\u{10FFFF}]x").unwrap()).sample(2, &mut rng).unwrap();
+    assert!(word.ends_with('x'));
+
+    // the same seed draws the same words
+    let draw = |seed| {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..10).map(|_| dfa.sample(6, &mut rng).unwrap()).collect::<Vec<_>>()
+    };
+    assert_eq!(draw(1), draw(1));
+
+    for _ in 0..200 {
+        let word = dfa.sample_walk(0.3, &mut rng).unwrap();
+        assert!(nfa.is_match(&word), "{}", word);
+    }
+    let keyword = DFA::from_nfa(&NFA::from_regex("if|else").unwrap());
+    assert!(matches!(keyword.sample_walk(0.0, &mut rng).as_deref(), Some("if") | Some("else")));
+    assert_eq!(keyword.intersect(&dfa).sample_walk(0.5, &mut rng), None);
+    assert_eq!(dfa.sample_walk(0.0, &mut rng), None);
+    assert_eq!(dfa.sample_walk(1.5, &mut rng), None);
+    assert_eq!(dfa.sample_walk(f64::NAN, &mut rng), None);
+    assert_eq!(dfa.sample_walk(1.0, &mut rng), Some(String::new()));
+}
+
+#[test]
+fn test_dfa_to_regex() {
+    for pattern in &["if|else|while", "[a-z_][a-z0-9_]*", "(a|ba)*b?", "0x[0-9a-f]+|[0-9]+", "[^\"]*x"] {
+        let dfa = DFA::from_nfa(&NFA::from_regex(pattern).unwrap()).minimize();
+        let regex = dfa.to_regex().unwrap().to_string();
+        let back = DFA::from_nfa(&NFA::from_regex(&regex).unwrap());
+        assert_eq!(dfa.equivalent(&back), Ok(()), "{} {}", pattern, regex);
+    }
+    let dfa = DFA::from_nfa(&NFA::from_regex("[a-z_][a-z0-9_]*").unwrap()).minimize();
+    assert_eq!(dfa.to_regex().unwrap().to_string(), "[_a-z][0-9_a-z]*");
+    let dfa = DFA::from_nfa(&NFA::from_regex("ab*").unwrap()).minimize();
+    assert_eq!(dfa.to_regex().unwrap().to_string(), "ab*");
+
+    // classes of control chars are written in a form Regex::parse reads back
+    for pattern in &["[^\\u{1}]", "[\\u{1}-\\u{3}]", "\\u{0}a*|[\\u{7f}-\\u{9f}]"] {
+        let dfa = DFA::from_nfa(&NFA::from_regex(pattern).unwrap()).minimize();
+        let regex = dfa.to_regex().unwrap().to_string();
+        let back = DFA::from_nfa(&NFA::from_regex(&regex).unwrap());
+        assert_eq!(dfa.equivalent(&back), Ok(()), "{} {}", pattern, regex);
+    }
+    assert_eq!(DFA::from_nfa(&NFA::from_regex("A").unwrap()).intersect(&dfa).to_regex(), None);
+
+    // the regex of a grammar
+    use crate::formal::FormalLanguage;
+    use std::io::BufReader;
+    use std::fs::File;
+    let data = FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test2.in").unwrap())).unwrap();
+    let formal = FormalLanguage::new(Token::Identifier("S".to_string()), data).unwrap();
+    let mut dfa = DFA::from_nfa(&formal.get_nfa());
+    dfa.simplifier();
+    let regex = dfa.to_regex().unwrap();
+    let expect = DFA::from_nfa(&NFA::from_regex("(a*bca*)?").unwrap());
+    assert_eq!(DFA::from_nfa(&NFA::from_regex_tree(&regex)).equivalent(&expect), Ok(()));
+}
@@ -1,35 +1,206 @@
-use std::collections::{HashSet, HashMap, VecDeque};
-use std::io::{BufRead, BufReader};
-use std::fs::File;
-use std::env;
+use std::collections::{HashSet, HashMap, VecDeque, BTreeSet};
+use std::io::{self, BufRead, Write};
+use std::{error, fmt};
 use crate::token::Token;
 use crate::nfa::NFA;
 use crate::dfa::DFA;
 
+/// the direction a regular grammer grows in
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GrammarKind {
+    /// productions like `A -> aB`
+    RightLinear,
+    /// productions like `A -> Ba`
+    LeftLinear,
+}
+
 pub struct FormalLanguage {
     start : Token,
     grammer : HashMap<Token, Vec<String>>,
+    kind : GrammarKind,
 }
 
 impl FormalLanguage {
-    pub fn new(start : Token, grammer : HashMap<Token, Vec<String>>) -> Self {
-        FormalLanguage {
+    /// the grammer kind is detected from the productions, a grammer using only
+    /// productions like `A -> a`, `A -> B` or `A -> ε` is read as right-linear.
+    /// the sentences are checked like get_grammer does, with errors at line 0:
+    /// an empty sentence is EmptyRight, one like `aAb` is TrailingSymbols, a
+    /// sentence whose kind differs from the one before is MixedLinearity and a
+    /// nonterminal used without productions is UndefinedNonterminal. get_grammer already rejects such files.
+    /// the start nonterminal may have no productions, then nothing is accepted
+    pub fn new(start : Token, grammer : HashMap<Token, Vec<String>>) -> Result<Self, GrammarError> {
+        let mut kind = None;
+        for sentence in grammer.values().flatten() {
+            if sentence.trim().is_empty() {
+                return Err(GrammarError::new(0, 0, sentence, GrammarErrorKind::EmptyRight));
+            }
+            match sentence_kind(sentence) {
+                Err(_) => {
+                    return Err(GrammarError::new(0, 0, sentence, GrammarErrorKind::TrailingSymbols));
+                }
+                Ok(Some(sentence_kind)) if *kind.get_or_insert(sentence_kind) != sentence_kind => {
+                    return Err(GrammarError::new(0, 0, sentence, GrammarErrorKind::MixedLinearity));
+                }
+                Ok(_) => {}
+            }
+            for symbol in split_sentence(sentence) {
+                if let Token::Identifier(name) = &symbol {
+                    if !grammer.contains_key(&symbol) {
+                        return Err(GrammarError::new(0, 0, name, GrammarErrorKind::UndefinedNonterminal));
+                    }
+                }
+            }
+        }
+        Ok(FormalLanguage {
             start,
             grammer,
-        } 
+            kind : kind.unwrap_or(GrammarKind::RightLinear),
+        })
     }
 
-    pub fn iter(&self) -> FormalLanguageIterator {
-        FormalLanguageIterator::new(&self.grammer, self.start.clone())       
+    pub fn get_kind(&self) -> GrammarKind {
+        self.kind
     }
 
+    /// iterate the (from, to, token) edges of the grammer. for a left-linear grammer
+    /// the edges belong to the reversed grammer, where `A -> Ba` is read as `A -> aB`
+    pub fn iter(&self) -> FormalLanguageIterator<'_> {
+        FormalLanguageIterator::new(&self.grammer, self.start.clone(), self.kind)       
+    }
+
+    /// a left-linear grammer is converted through its reversed grammer, whose
+    /// NFA accepts the reversed language, so the NFA is built with every edge flipped
     pub fn get_nfa(&self) -> NFA{
-        let mut nfa = NFA::new(1, 0);
-        self.iter()
-            .for_each(|tuple| {
-                nfa.push(tuple.0, (tuple.2, tuple.1))
-            });
-        nfa
+        match self.kind {
+            GrammarKind::RightLinear => {
+                let mut nfa = NFA::new(1, 0);
+                self.iter()
+                    .for_each(|tuple| {
+                        nfa.push(tuple.0, (tuple.2, tuple.1))
+                    });
+                nfa
+            }
+            GrammarKind::LeftLinear => {
+                let mut nfa = NFA::new(0, 1);
+                self.iter()
+                    .for_each(|tuple| {
+                        nfa.push(tuple.1, (tuple.2, tuple.0))
+                    });
+                nfa
+            }
+        }
+    }
+
+    /// build the right-linear grammer of the NFA. the start point is `S`, the other
+    /// points are named `A` to `Z` and then `<N1>`, `<N2>`..., and every edge becomes
+    /// a production like `A -> aB`, or `A -> B` for an epsilon edge. a class becomes
+    /// one alternative for every char. the points which can't reach an accepting
+    /// point are left out, and so are Token::Identifier edges, which match no char.
+    /// a char which can't be written as a terminal, like an uppercase letter, `|`
+    /// or a space, is rejected with an UnwritableTerminal error at line 0, so a wide
+    /// class like `.` or `[^"]` is always rejected. if nothing is accepted `S` has
+    /// no productions, and write_grammer writes an empty file
+    pub fn from_nfa(nfa : &NFA) -> Result<Self, GrammarError> {
+        // the points on a path from a start to an accepting point, which doesn't
+        // take the dropped Token::Identifier edges, so every point kept but the
+        // start gets a production
+        let mut reachable : BTreeSet<u64> = nfa.get_starts().clone();
+        let mut queue : VecDeque<u64> = reachable.iter().cloned().collect();
+        let mut incoming : HashMap<u64, Vec<u64>> = HashMap::new();
+        while let Some(id) = queue.pop_front() {
+            let edges = nfa.get_node(id).unwrap().get_edges()
+                .iter()
+                .filter(|(token, _)| !matches!(token, Token::Identifier(_)));
+            for (_, to) in edges {
+                incoming.entry(*to).or_default().push(id);
+                if reachable.insert(*to) {
+                    queue.push_back(*to);
+                }
+            }
+        }
+        let mut useful : BTreeSet<u64> = nfa.get_accepting().intersection(&reachable).cloned().collect();
+        let mut queue : VecDeque<u64> = useful.iter().cloned().collect();
+        while let Some(id) = queue.pop_front() {
+            for from in incoming.get(&id).into_iter().flatten() {
+                if useful.insert(*from) {
+                    queue.push_back(*from);
+                }
+            }
+        }
+
+        let start = nfa.get_start();
+        let mut names : HashMap<u64, String> = HashMap::new();
+        names.insert(start, "S".to_string());
+        let mut letters = ('A'..='Z').filter(|letter| *letter != 'S');
+        let mut num = 0;
+        for id in &useful {
+            if *id != start {
+                let name = letters.next().map(|letter| letter.to_string()).unwrap_or_else(|| {
+                    num += 1;
+                    format!("<N{}>", num)
+                });
+                names.insert(*id, name);
+            }
+        }
+
+        let mut grammer : HashMap<Token, Vec<String>> = HashMap::new();
+        let start_sentences = grammer.entry(Token::Identifier("S".to_string())).or_default();
+        for other in nfa.get_starts() {
+            if *other != start && useful.contains(other) {
+                start_sentences.push(names.get(other).unwrap().clone());
+            }
+        }
+        for id in &useful {
+            let sentences = grammer.entry(Token::Identifier(names.get(id).unwrap().clone())).or_default();
+            for (token, to) in nfa.get_node(*id).unwrap().get_edges() {
+                let to = match names.get(to) {
+                    Some(to) => to,
+                    None => continue,
+                };
+                match token {
+                    Token::Epsilon => sentences.push(to.clone()),
+                    Token::Identifier(_) => {}
+                    _ => {
+                        for (first, last) in token.ranges() {
+                            for ch in first..=last {
+                                if !is_terminal_char(ch) {
+                                    let text = ch.to_string();
+                                    let kind = GrammarErrorKind::UnwritableTerminal(ch);
+                                    return Err(GrammarError::new(0, 0, &text, kind));
+                                }
+                                sentences.push(format!("{}{}", ch, to));
+                            }
+                        }
+                    }
+                }
+            }
+            if nfa.is_accepting(*id) {
+                sentences.push("ε".to_string());
+            }
+        }
+        FormalLanguage::new(Token::Identifier("S".to_string()), grammer)
+    }
+
+    /// build the right-linear grammer of the DFA, see from_nfa
+    pub fn from_dfa(dfa : &DFA) -> Result<Self, GrammarError> {
+        FormalLanguage::from_nfa(&dfa.to_nfa())
+    }
+
+    /// write the productions in the format get_grammer reads, one line for every
+    /// nonterminal like `A -> aB | ε`, starting with the start nonterminal
+    pub fn write_grammer<W : Write>(&self, writer : &mut W) -> io::Result<()> {
+        let mut nonterminals : Vec<&Token> = self.grammer.keys().collect();
+        nonterminals.sort_by_key(|token| match token {
+            Token::Identifier(name) => (**token != self.start, name.len(), name.clone()),
+            _ => (true, 0, String::new()),
+        });
+        for token in nonterminals {
+            let sentences = self.grammer.get(token).unwrap();
+            if let (Token::Identifier(name), false) = (token, sentences.is_empty()) {
+                writeln!(writer, "{} -> {}", name, sentences.join(" | "))?;
+            }
+        }
+        Ok(())
     }
 
     pub fn print(&self) {
@@ -39,38 +210,273 @@ impl FormalLanguage {
             })
     }
 
-    pub fn get_grammer<R>(reader : &mut R) -> HashMap<Token, Vec<String>> 
+    /// read the grammer from `reader`. every line is a production like `S -> aA | bB | ε`,
+    /// `#` starts a comment and blank lines are skipped. a line ending with `\` or a
+    /// line starting with `|` continues the previous production.
+    /// return a GrammarError describing the first malformed line instead of panicking
+    pub fn get_grammer<R>(reader : &mut R) -> Result<HashMap<Token, Vec<String>>, GrammarError>
     where 
         R : BufRead
     {
         let mut map : HashMap<Token, Vec<String>> = HashMap::new();
         let mut buf = String::new();
-        while reader.read_line(&mut buf).unwrap() != 0 {
-            let data : Vec<_> = buf.trim()
-                .split("->")
-                .map(str::trim)
-                .collect();
-            println!("{:?}", data);
-            if data.len() != 2 {
-                panic!("wrong grammer!");
-            }
-
-            let token = Token::Identifier(data[0].chars().next().unwrap());
-            
-            if let Some(nodes) = map.get_mut(&token) {
-                nodes.push(data[1].to_string());
-            } else {
-                let mut nodes = Vec::new();
-                nodes.push(data[1].to_string());
-                map.insert(token, nodes);
-            }
+        // the production being collected and where each of its lines starts, see
+        // parse_production
+        let mut pending : Option<(String, Vec<(usize, usize)>)> = None;
+        let mut kind = None;
+        // the first use of every nonterminal, an error if it is never defined
+        let mut uses = Vec::new();
+        let mut joined = false;
+        let mut line = 0;
+        loop {
+            line += 1;
             buf.clear();
+            match reader.read_line(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => {},
+                Err(err) => {
+                    return Err(GrammarError::new(line, 0, "", GrammarErrorKind::Io(err)));
+                }
+            }
+
+            let mut text = buf.trim_end_matches(&['\r', '\n'][..]);
+            if let Some(comment) = text.find('#') {
+                text = &text[..comment];
+            }
+            let continued = joined || text.trim_start().starts_with('|');
+            joined = text.trim_end().ends_with('\\');
+            if joined {
+                text = &text.trim_end()[..text.trim_end().len() - 1];
+            }
+
+            match &mut pending {
+                Some((production, lines)) if continued => {
+                    production.push(' ');
+                    lines.push((production.chars().count() + 1, line));
+                    production.push_str(text);
+                    continue;
+                }
+                _ if text.trim().is_empty() && !joined => continue,
+                _ => {}
+            }
+            if let Some((production, lines)) = pending.replace((text.to_string(), vec![(1, line)])) {
+                insert_production(&mut map, &mut kind, &mut uses, &production, &lines)?;
+            }
+        }
+        if let Some((production, lines)) = pending {
+            insert_production(&mut map, &mut kind, &mut uses, &production, &lines)?;
+        }
+        if let Some((_, err)) = uses.into_iter().find(|(token, _)| !map.contains_key(token)) {
+            return Err(err);
+        }
+
+        Ok(map)
+    }
+}
+
+fn insert_production(
+    map : &mut HashMap<Token, Vec<String>>, 
+    kind : &mut Option<GrammarKind>,
+    uses : &mut Vec<(Token, GrammarError)>,
+    text : &str, 
+    lines : &[(usize, usize)]
+) -> Result<(), GrammarError>
+{
+    let (token, sentences) = parse_production(text, kind, uses, lines)?;
+    map.entry(token)
+        .or_default()
+        .extend(sentences);
+    Ok(())
+}
+
+/// split one `A -> aB | b` production into its nonterminal and its alternatives.
+/// `text` is the production after continuation lines are joined and `lines` holds
+/// the (column in `text`, line) where every joined line starts, so errors point at
+/// the line and column in the file.
+/// `kind` is the grammer kind seen so far, a sentence of the other kind is rejected.
+/// the first use of every nonterminal is added to `uses` with the error to report
+/// if it turns out to be undefined
+fn parse_production(
+    text : &str,
+    kind : &mut Option<GrammarKind>,
+    uses : &mut Vec<(Token, GrammarError)>,
+    lines : &[(usize, usize)]
+) -> Result<(Token, Vec<String>), GrammarError> 
+{
+    let column_of = |offset : usize| text[..offset].chars().count() + 1;
+    let error = |column : usize, text : &str, kind| {
+        let (start, line) = lines.iter()
+            .rev()
+            .find(|(start, _)| *start <= column)
+            .unwrap_or(&lines[0]);
+        GrammarError::new(*line, column - start + 1, text, kind)
+    };
+
+    let arrow = match text.find("->") {
+        Some(arrow) => arrow,
+        None => return Err(error(1, text, GrammarErrorKind::MissingArrow)),
+    };
+    if let Some(extra) = text[arrow + 2..].find("->") {
+        let column = column_of(arrow + 2 + extra);
+        return Err(error(column, text, GrammarErrorKind::ExtraArrow));
+    }
+
+    let left = text[..arrow].trim();
+    let left_offset = text.len() - text.trim_start().len();
+    if left.is_empty() {
+        return Err(error(column_of(arrow), text, GrammarErrorKind::EmptyLeft));
+    }
+    let name = match split_sentence(left).as_slice() {
+        [Token::Identifier(name)] => name.clone(),
+        _ => {
+            let column = column_of(left_offset);
+            return Err(error(column, left, GrammarErrorKind::InvalidNonterminal));
+        }
+    };
+
+    let mut sentences = Vec::new();
+    let mut offset = arrow + 2;
+    for alternative in text[arrow + 2..].split('|') {
+        let sentence = alternative.trim();
+        if sentence.is_empty() {
+            return Err(error(column_of(offset), text, GrammarErrorKind::EmptyRight));
+        }
+        let column = column_of(offset + alternative.len() - alternative.trim_start().len());
+        match sentence_kind(sentence) {
+            Err(trailing) => {
+                let column = column + trailing;
+                return Err(error(column, sentence, GrammarErrorKind::TrailingSymbols));
+            }
+            Ok(Some(sentence_kind)) if *kind.get_or_insert(sentence_kind) != sentence_kind => {
+                return Err(error(column, sentence, GrammarErrorKind::MixedLinearity));
+            }
+            Ok(_) => {}
         }
+        for (index, symbol) in split_sentence_at(sentence) {
+            if let Token::Identifier(name) = &symbol {
+                if uses.iter().all(|(used, _)| *used != symbol) {
+                    let err = error(column + index, name, GrammarErrorKind::UndefinedNonterminal);
+                    uses.push((symbol, err));
+                }
+            }
+        }
+        sentences.push(sentence.to_string());
+        offset += alternative.len() + 1;
+    }
+
+    Ok((Token::Identifier(name), sentences))
+}
+
+/// a right-linear sentence is some terminals followed by one nonterminal, a left-linear
+/// one is a nonterminal followed by some terminals. `ε`, only terminals or only a
+/// nonterminal fit both and return None. return the char index of the first symbol
+/// breaking both shapes as the error
+fn sentence_kind(sentence : &str) -> Result<Option<GrammarKind>, usize> {
+    let symbols = split_sentence_at(sentence);
+    if let [(_, Token::Epsilon)] = symbols.as_slice() {
+        return Ok(None);
+    }
+    if let Some((index, _)) = symbols.iter().find(|(_, symbol)| *symbol == Token::Epsilon) {
+        return Err(*index);
+    }
 
-        map
+    let nonterminals : Vec<usize> = symbols.iter()
+        .enumerate()
+        .filter(|(_, (_, symbol))| matches!(symbol, Token::Identifier(_)))
+        .map(|(i, _)| i)
+        .collect();
+    match nonterminals.as_slice() {
+        [] => Ok(None),
+        [_] if symbols.len() == 1 => Ok(None),
+        [i] if *i + 1 == symbols.len() => Ok(Some(GrammarKind::RightLinear)),
+        [0] => Ok(Some(GrammarKind::LeftLinear)),
+        // `aAb` or `ABa`, report the symbol after the first misplaced nonterminal
+        [i, ..] if *i == 0 => Err(symbols[1].0),
+        [i, ..] => Err(symbols[*i + 1].0),
     }
+}
 
+/// the reason a grammer line was rejected
+#[derive(Debug)]
+pub enum GrammarErrorKind {
+    /// the line has no `->`
+    MissingArrow,
+    /// the line has more than one `->`
+    ExtraArrow,
+    /// nothing before the `->`
+    EmptyLeft,
+    /// the text before the `->` is not a nonterminal like `A`, `<Expr>` or `Digit'`
+    InvalidNonterminal,
+    /// nothing after the `->` or between two `|`
+    EmptyRight,
+    /// the sentence is neither right-linear nor left-linear, like `aAb`,
+    /// or an `ε` is mixed with other symbols
+    TrailingSymbols,
+    /// a left-linear sentence in a right-linear grammer, or the other way around
+    MixedLinearity,
+    /// a nonterminal used in a sentence without any production of its own
+    UndefinedNonterminal,
+    /// a char of an automaton which the grammer format would read as something
+    /// else than a terminal, reported by FormalLanguage::from_nfa
+    UnwritableTerminal(char),
+    /// reading the line failed
+    Io(io::Error),
+}
 
+/// error returned by FormalLanguage::get_grammer, `line` and `column` are 1-based,
+/// `text` is the offending part of the line. FormalLanguage::new has no lines and
+/// reports line and column 0
+#[derive(Debug)]
+pub struct GrammarError {
+    pub line : usize,
+    pub column : usize,
+    pub text : String,
+    pub kind : GrammarErrorKind,
+}
+
+impl GrammarError {
+    fn new(line : usize, column : usize, text : &str, kind : GrammarErrorKind) -> Self {
+        GrammarError {
+            line,
+            column,
+            text : text.to_string(),
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let reason = match &self.kind {
+            GrammarErrorKind::MissingArrow => "missing `->`".to_string(),
+            GrammarErrorKind::ExtraArrow => "more than one `->`".to_string(),
+            GrammarErrorKind::EmptyLeft => "empty left side".to_string(),
+            GrammarErrorKind::InvalidNonterminal => "invalid nonterminal".to_string(),
+            GrammarErrorKind::EmptyRight => "empty right side".to_string(),
+            GrammarErrorKind::TrailingSymbols => "unexpected symbol after the nonterminal".to_string(),
+            GrammarErrorKind::MixedLinearity => {
+                "left-linear and right-linear productions are mixed".to_string()
+            }
+            GrammarErrorKind::UndefinedNonterminal => "undefined nonterminal".to_string(),
+            GrammarErrorKind::UnwritableTerminal(ch) => {
+                format!("{:?} can't be written as a terminal", ch)
+            }
+            GrammarErrorKind::Io(err) => return write!(f, "line {}: {}", self.line, err),
+        };
+        if self.line == 0 {
+            return write!(f, "{} in `{}`", reason, self.text);
+        }
+        write!(f, "line {}, column {}: {} in `{}`", self.line, self.column, reason, self.text)
+    }
+}
+
+impl error::Error for GrammarError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.kind {
+            GrammarErrorKind::Io(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
 pub struct FormalLanguageIterator<'a> {
@@ -82,10 +488,13 @@ pub struct FormalLanguageIterator<'a> {
     queue : VecDeque<Token>,
     num : u64,
     end : u64, 
+    /// edges of a multi-character sentence which are not returned yet
+    pending : VecDeque<(u64, u64, Token)>,
+    kind : GrammarKind,
 }
 
 impl<'a> FormalLanguageIterator<'a> {
-    fn new(grammer : &'a HashMap<Token, Vec<String>>, start : Token) -> Self {
+    fn new(grammer : &'a HashMap<Token, Vec<String>>, start : Token, kind : GrammarKind) -> Self {
         let mut token_map = HashMap::new();
         let mut visited  = HashSet::new(); 
         let mut queue = VecDeque::new();
@@ -105,17 +514,38 @@ impl<'a> FormalLanguageIterator<'a> {
             queue,
             num,
             end,
+            pending : VecDeque::new(),
+            kind,
         } 
     }
+
+    /// get the state id of the nonterminal, and schedule it to be visited
+    fn nonterminal_id(&mut self, token : Token) -> u64 {
+        if self.visited.insert(token.clone()) {
+            self.queue.push_back(token.clone());
+        }
+        match self.token_map.get(&token) {
+            Some(t) => *t,
+            None => {
+                self.num += 1;
+                self.token_map.insert(token, self.num);
+                self.num
+            }
+        }
+    }
 }
 
 impl<'a> Iterator for FormalLanguageIterator<'a> {
     type Item = (u64, u64, Token);
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(edge) = self.pending.pop_front() {
+            return Some(edge);
+        }
+        // a nonterminal without productions, like a start accepting nothing, has no edges
         while self.pos.is_none() || self.grammer.get(&self.tmp)
-            .expect("unknown grammer")
-            .get(self.pos.unwrap() as usize).is_none()
+            .and_then(|sentences| sentences.get(self.pos.unwrap() as usize))
+            .is_none()
         {
             if !self.queue.is_empty() {
                 self.tmp = self.queue.pop_front().unwrap();
@@ -125,68 +555,106 @@ impl<'a> Iterator for FormalLanguageIterator<'a> {
             }
         }
         
-        let sentence = self.grammer.get(&self.tmp)
+        let grammer = self.grammer;
+        let sentence = grammer.get(&self.tmp)
             .unwrap()
             .get(self.pos.unwrap() as usize).unwrap();
 
 
         let tmpid = *self.token_map.get(&self.tmp).unwrap();
-        let mut iter = sentence.chars();
-        let first = iter.next().expect("wrong sentence");
-        if let Some(second) = iter.next() {
-            let token = Token::Identifier(second);
-            if let None = self.visited.get(&token) {
-                self.visited.insert(token.clone());
-                self.queue.push_back(token.clone());
-            }
-            let to = match self.token_map.get(&token) {
-                Some(t) => *t,
-                None => {
-                    self.num += 1;
-                    self.token_map.insert(token, self.num);
-                    self.num
-                }
-            };
-            self.pos = Some(self.pos.unwrap() + 1);
-            return Some((tmpid, to, Token::Character(first)));
-        } else {
-            self.pos = Some(self.pos.unwrap() + 1);
-            if is_identifier(first) {
-                let token = Token::Identifier(first);
-                if let None = self.visited.get(&token) {
-                    self.visited.insert(token.clone());
-                    self.queue.push_back(token.clone());
-                }
-                let to = match self.token_map.get(&token) {
-                    Some(t) => *t,
-                    None => {
-                        self.num += 1;
-                        self.token_map.insert(token, self.num);
-                        self.num
-                    }
-                };
-                return Some((tmpid, to, Token::Epsilon));
-            } else if first == 'ε' {
-                return Some((tmpid, self.end, Token::Epsilon));
-            } else {
-                return Some((tmpid, self.end, Token::Character(first)));
+        self.pos = Some(self.pos.unwrap() + 1);
+        let mut symbols = split_sentence(sentence);
+        if self.kind == GrammarKind::LeftLinear {
+            symbols.reverse();
+        }
+        let (terminals, to) = match symbols.split_last() {
+            Some((Token::Epsilon, [])) => return Some((tmpid, self.end, Token::Epsilon)),
+            Some((Token::Identifier(name), terminals)) => {
+                (terminals, self.nonterminal_id(Token::Identifier(name.clone())))
             }
+            Some((Token::Character(_), _)) => (&symbols[..], self.end),
+            _ => panic!("wrong sentence {}", sentence),
+        };
+        if terminals.is_empty() {
+            return Some((tmpid, to, Token::Epsilon));
         }
+
+        // `whileA` becomes a chain of fresh states, one for every terminal
+        let mut from = tmpid;
+        for (i, terminal) in terminals.iter().enumerate() {
+            let next = if i + 1 == terminals.len() {
+                to
+            } else {
+                self.num += 1;
+                self.num
+            };
+            self.pending.push_back((from, next, terminal.clone()));
+            from = next;
+        }
+        self.pending.pop_front()
     }
 }
 
-fn is_identifier(ch : char) -> bool {
-    match ch {
-        'A'..='Z' =>  true,
-        _ => false,
+/// whether `ch` is read back as a terminal by split_sentence and get_grammer
+fn is_terminal_char(ch : char) -> bool {
+    !(ch.is_ascii_uppercase() || ch.is_whitespace() || "<>|#\\-ε".contains(ch))
+}
+
+/// split the sentence into terminals and nonterminals. a nonterminal is a single
+/// uppercase letter, a name in angle brackets like `<Expr>`, or an uppercase
+/// identifier followed by primes like `Digit'`. `ε` becomes Token::Epsilon
+fn split_sentence(sentence : &str) -> Vec<Token> {
+    split_sentence_at(sentence)
+        .into_iter()
+        .map(|(_, symbol)| symbol)
+        .collect()
+}
+
+/// same as split_sentence, but every symbol comes with its char index in the sentence
+fn split_sentence_at(sentence : &str) -> Vec<(usize, Token)> {
+    let chars : Vec<char> = sentence.chars().collect();
+    let mut symbols = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        if ch == '<' {
+            if let Some(len) = chars[i..].iter().position(|c| *c == '>') {
+                symbols.push((i, Token::Identifier(chars[i..=i + len].iter().collect())));
+                i += len + 1;
+                continue;
+            }
+        } else if ch.is_ascii_uppercase() {
+            let mut end = i + 1;
+            while end < chars.len() && (chars[end].is_ascii_alphanumeric() || chars[end] == '_') {
+                end += 1;
+            }
+            let mut primed = end;
+            while primed < chars.len() && chars[primed] == '\'' {
+                primed += 1;
+            }
+            let end = if primed > end { primed } else { i + 1 };
+            symbols.push((i, Token::Identifier(chars[i..end].iter().collect())));
+            i = end;
+            continue;
+        }
+
+        if ch == 'ε' {
+            symbols.push((i, Token::Epsilon));
+        } else if !ch.is_whitespace() {
+            symbols.push((i, Token::Character(ch)));
+        }
+        i += 1;
     }
+    symbols
 }
 
 
 #[test]
 fn test_normal_language() {
-    let data = FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test.in").unwrap()));
-    let formal = FormalLanguage::new(Token::Identifier('S'), data);
+    use std::io::BufReader;
+    use std::fs::File;
+    let data = FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test.in").unwrap())).unwrap();
+    let formal = FormalLanguage::new(Token::Identifier("S".to_string()), data).unwrap();
     formal.print();
     let nfa = formal.get_nfa();
     println!("0 test result : {:?}", nfa.get_epsilon_closure_node(0));
@@ -199,18 +667,274 @@ fn test_normal_language() {
 
 #[test]
 fn test_dfa() {
-    let data = FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test2.in").unwrap()));
-    let formal = FormalLanguage::new(Token::Identifier('S'), data);
+    use std::io::BufReader;
+    use std::fs::File;
+    use crate::dfa::DFA;
+    let data = FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test2.in").unwrap())).unwrap();
+    let formal = FormalLanguage::new(Token::Identifier("S".to_string()), data).unwrap();
     formal.print();
     let nfa = formal.get_nfa();
     let mut dfa = DFA::from_nfa(&nfa);
     dfa.print();
     dfa.simplifier();
     dfa.print();
-    use std::fs::File;
     let mut output = File::create("example2.dot").unwrap();
     dot::render(&dfa, &mut output).unwrap();
-    println!("match pattern to {} is {}", "aaaabcaab", dfa.scanner("aaaabcaab"));
-    println!("match pattern to {} is {}", "aaabbcaaaaaab", dfa.scanner("aaabbcaaaaaab"));
+    println!("match pattern to aaaabcaab is {}", dfa.scanner("aaaabcaab"));
+    println!("match pattern to aaabbcaaaaaab is {}", dfa.scanner("aaabbcaaaaaab"));
+
+}
+
+#[test]
+fn test_grammer_error() {
+    use std::io::Cursor;
+    let check = |input : &str, line : usize, column : usize| {
+        let err = FormalLanguage::get_grammer(&mut Cursor::new(input)).unwrap_err();
+        assert_eq!((err.line, err.column), (line, column), "{}", err);
+        err.kind
+    };
+
+    assert!(matches!(check("S -> aA\nS aA\n", 2, 1), GrammarErrorKind::MissingArrow));
+    assert!(matches!(check("S -> a -> A\n", 1, 8), GrammarErrorKind::ExtraArrow));
+    assert!(matches!(check("  -> aA\n", 1, 3), GrammarErrorKind::EmptyLeft));
+    assert!(matches!(check("S -> aA\n  SA -> a\n", 2, 3), GrammarErrorKind::InvalidNonterminal));
+    assert!(matches!(check("S -> aA\nA ->  \n", 2, 5), GrammarErrorKind::EmptyRight));
 
+    // errors in continuation lines point at the line they are on
+    assert!(matches!(check("S -> a | \\\n   aAb\n", 2, 6), GrammarErrorKind::TrailingSymbols));
+    assert!(matches!(check("S -> aA\n  | bB\n  |  | c\n", 3, 4), GrammarErrorKind::EmptyRight));
+    assert!(matches!(check("S -> \\\n a -> b\n", 2, 4), GrammarErrorKind::ExtraArrow));
+    assert!(matches!(check("S -> aA | b\nB -> ε | a<Rest>\n", 1, 7), GrammarErrorKind::UndefinedNonterminal));
+    assert!(matches!(check("S -> aS | b\nB -> ε | a<Rest>\n", 2, 11), GrammarErrorKind::UndefinedNonterminal));
+
+    let mut grammer = HashMap::new();
+    grammer.insert(Token::Identifier("S".to_string()), vec!["aA".to_string()]);
+    let err = FormalLanguage::new(Token::Identifier("S".to_string()), grammer).err().unwrap();
+    assert!(matches!(err.kind, GrammarErrorKind::UndefinedNonterminal));
+    assert_eq!(err.text, "A");
+    // a start without productions accepts nothing
+    let nfa = FormalLanguage::new(Token::Identifier("S".to_string()), HashMap::new()).unwrap().get_nfa();
+    assert!(!nfa_accepts(&nfa, "") && !nfa_accepts(&nfa, "a"));
+
+    let err = FormalLanguage::get_grammer(&mut Cursor::new(&b"S -> a\xff\n"[..])).unwrap_err();
+    assert!(matches!(err.kind, GrammarErrorKind::Io(_)));
+    assert_eq!(err.line, 1);
+}
+
+#[test]
+fn test_grammer_alternatives() {
+    use std::io::BufReader;
+    use std::fs::File;
+    let mut expect = FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test.in").unwrap())).unwrap();
+    let mut data = FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test3.in").unwrap())).unwrap();
+    expect.values_mut().for_each(|sentences| sentences.sort());
+    data.values_mut().for_each(|sentences| sentences.sort());
+    assert_eq!(data, expect);
+
+    let err = FormalLanguage::get_grammer(&mut std::io::Cursor::new("\n# S\nS -> a | | b\n")).unwrap_err();
+    assert!(matches!(err.kind, GrammarErrorKind::EmptyRight));
+    assert_eq!((err.line, err.column), (3, 9));
+}
+
+#[test]
+fn test_named_nonterminal() {
+    use std::io::Cursor;
+    let input = "<Id> -> a<Rest> | bRest'\n<Rest> -> aRest' | ε\nRest' -> b<Rest>\n";
+    let data = FormalLanguage::get_grammer(&mut Cursor::new(input)).unwrap();
+    let mut names : Vec<_> = data.keys().cloned().collect();
+    names.sort();
+    assert_eq!(names, vec![
+        Token::Identifier("<Id>".to_string()),
+        Token::Identifier("<Rest>".to_string()),
+        Token::Identifier("Rest'".to_string()),
+    ]);
+    assert_eq!(split_sentence("aRest'"), vec![Token::Character('a'), Token::Identifier("Rest'".to_string())]);
+    assert_eq!(split_sentence("a<"), vec![Token::Character('a'), Token::Character('<')]);
+
+    let formal = FormalLanguage::new(Token::Identifier("<Id>".to_string()), data).unwrap();
+    let mut edges : Vec<_> = formal.iter().collect();
+    edges.sort();
+    assert_eq!(edges, vec![
+        (1, 2, Token::Character('a')),
+        (1, 3, Token::Character('b')),
+        (2, 0, Token::Epsilon),
+        (2, 3, Token::Character('a')),
+        (3, 2, Token::Character('b')),
+    ]);
+}
+
+#[test]
+fn test_multi_character_terminals() {
+    use std::io::Cursor;
+    let data = FormalLanguage::get_grammer(&mut Cursor::new("S -> whileA | do\nA -> ε\n")).unwrap();
+    let formal = FormalLanguage::new(Token::Identifier("S".to_string()), data).unwrap();
+    let edges : Vec<_> = formal.iter().collect();
+    assert_eq!(edges, vec![
+        (1, 3, Token::Character('w')),
+        (3, 4, Token::Character('h')),
+        (4, 5, Token::Character('i')),
+        (5, 6, Token::Character('l')),
+        (6, 2, Token::Character('e')),
+        (1, 7, Token::Character('d')),
+        (7, 0, Token::Character('o')),
+        (2, 0, Token::Epsilon),
+    ]);
+
+    let err = FormalLanguage::get_grammer(&mut Cursor::new("S -> aAb\n")).unwrap_err();
+    assert!(matches!(err.kind, GrammarErrorKind::TrailingSymbols));
+    assert_eq!((err.line, err.column), (1, 8));
+    let err = FormalLanguage::get_grammer(&mut Cursor::new("S -> a | aε\n")).unwrap_err();
+    assert!(matches!(err.kind, GrammarErrorKind::TrailingSymbols));
+    assert_eq!((err.line, err.column), (1, 11));
+}
+
+#[cfg(test)]
+fn nfa_accepts(nfa : &NFA, word : &str) -> bool {
+    let mut states : HashSet<u64> = nfa.get_epsilon_closure_node(nfa.get_start()).unwrap()
+        .into_iter()
+        .collect();
+    for ch in word.chars() {
+        states = states.iter()
+            .flat_map(|id| nfa.get_node(*id).unwrap().get_edges().iter())
+            .filter(|(token, _)| *token == Token::Character(ch))
+            .flat_map(|(_, to)| nfa.get_epsilon_closure_node(*to).unwrap())
+            .collect();
+    }
+    states.iter().any(|id| nfa.is_accepting(*id))
+}
+
+#[test]
+fn test_left_linear() {
+    use std::io::{BufReader, Cursor};
+    use std::fs::File;
+    let right = FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test2.in").unwrap())).unwrap();
+    let right = FormalLanguage::new(Token::Identifier("S".to_string()), right).unwrap();
+    let input = "S -> E | ε\nE -> Dc | Ea\nD -> Ab\nA -> Aa | ε\n";
+    let left = FormalLanguage::get_grammer(&mut Cursor::new(input)).unwrap();
+    let left = FormalLanguage::new(Token::Identifier("S".to_string()), left).unwrap();
+    assert_eq!(right.get_kind(), GrammarKind::RightLinear);
+    assert_eq!(left.get_kind(), GrammarKind::LeftLinear);
+
+    let (right, left) = (right.get_nfa(), left.get_nfa());
+    for word in &["", "bc", "aabca", "bcaaa", "abc", "b", "ab", "bcb", "cb", "aabcab"] {
+        assert_eq!(nfa_accepts(&left, word), nfa_accepts(&right, word), "{}", word);
+    }
+    assert!(nfa_accepts(&left, "aabcaa"));
+    assert!(!nfa_accepts(&left, "aabcb"));
+
+    let left = FormalLanguage::get_grammer(&mut Cursor::new("S -> <Id>while\n<Id> -> x\n")).unwrap();
+    let left = FormalLanguage::new(Token::Identifier("S".to_string()), left).unwrap().get_nfa();
+    assert!(nfa_accepts(&left, "xwhile"));
+    assert!(!nfa_accepts(&left, "whilex"));
+
+    let err = FormalLanguage::get_grammer(&mut Cursor::new("S -> aA\nA -> Sb | ε\n")).unwrap_err();
+    assert!(matches!(err.kind, GrammarErrorKind::MixedLinearity));
+    assert_eq!((err.line, err.column), (2, 6));
+
+    // a caller-built grammer is rejected by new instead of panicking
+    let mut mixed = HashMap::new();
+    mixed.insert(Token::Identifier("S".to_string()), vec!["aA".to_string()]);
+    mixed.insert(Token::Identifier("A".to_string()), vec!["Sb".to_string()]);
+    let err = FormalLanguage::new(Token::Identifier("S".to_string()), mixed).err().unwrap();
+    assert!(matches!(err.kind, GrammarErrorKind::MixedLinearity));
+    assert_eq!(err.line, 0);
+    assert!(err.to_string().starts_with("left-linear and right-linear productions are mixed"));
+
+    let check = |sentence : &str| {
+        let mut grammer = HashMap::new();
+        grammer.insert(Token::Identifier("S".to_string()), vec![sentence.to_string()]);
+        grammer.insert(Token::Identifier("A".to_string()), vec!["ε".to_string()]);
+        FormalLanguage::new(Token::Identifier("S".to_string()), grammer).err().map(|err| err.kind)
+    };
+    assert!(matches!(check("aAb"), Some(GrammarErrorKind::TrailingSymbols)));
+    assert!(matches!(check("aε"), Some(GrammarErrorKind::TrailingSymbols)));
+    assert!(matches!(check(""), Some(GrammarErrorKind::EmptyRight)));
+    assert!(matches!(check("  "), Some(GrammarErrorKind::EmptyRight)));
+    assert!(check("abA").is_none() && check("Aab").is_none());
+}
+
+#[test]
+fn test_grammer_equivalent() {
+    use std::io::{BufReader, Cursor};
+    use std::fs::File;
+    use crate::dfa::DFA;
+    let language = |data| DFA::from_nfa(&FormalLanguage::new(Token::Identifier("S".to_string()), data).unwrap().get_nfa());
+    let before = language(FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test.in").unwrap())).unwrap());
+    let after = language(FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test3.in").unwrap())).unwrap());
+    assert_eq!(before.equivalent(&after), Ok(()));
+
+    let right = language(FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test2.in").unwrap())).unwrap());
+    let left = language(FormalLanguage::get_grammer(&mut Cursor::new("S -> E | ε\nE -> Dc | Ea\nD -> Ab\nA -> Aa | ε\n")).unwrap());
+    assert_eq!(left.equivalent(&right), Ok(()));
+
+    // forgetting `B -> ε` loses the words ending in B
+    let edited = language(FormalLanguage::get_grammer(&mut Cursor::new("S -> aA | bB | ε\nA -> aB | bA\nB -> aS | bA\n")).unwrap());
+    assert_eq!(before.equivalent(&edited), Err("b".to_string()));
+}
+
+#[test]
+fn test_from_automata() {
+    use std::io::{BufReader, Cursor};
+    use std::fs::File;
+    let read = |text : &[u8]| {
+        let data = FormalLanguage::get_grammer(&mut Cursor::new(text)).unwrap();
+        DFA::from_nfa(&FormalLanguage::new(Token::Identifier("S".to_string()), data).unwrap().get_nfa())
+    };
+
+    // the minimized automaton of test2.in saved back as a grammer file
+    let data = FormalLanguage::get_grammer(&mut BufReader::new(&mut File::open("test2.in").unwrap())).unwrap();
+    let dfa = DFA::from_nfa(&FormalLanguage::new(Token::Identifier("S".to_string()), data).unwrap().get_nfa()).minimize();
+    let formal = FormalLanguage::from_dfa(&dfa).unwrap();
+    assert_eq!(formal.get_kind(), GrammarKind::RightLinear);
+    let mut text = Vec::new();
+    formal.write_grammer(&mut text).unwrap();
+    assert_eq!(String::from_utf8(text.clone()).unwrap(), "S -> aA | bB | ε\nA -> aA | bB\nB -> cC\nC -> aC | ε\n");
+    assert_eq!(read(&text).equivalent(&dfa), Ok(()));
+
+    // epsilon edges, classes and several start points
+    let nfa = NFA::from_regex("(a[b-d])*|e?f").unwrap();
+    let mut text = Vec::new();
+    FormalLanguage::from_nfa(&nfa).unwrap().write_grammer(&mut text).unwrap();
+    assert_eq!(read(&text).equivalent(&DFA::from_nfa(&nfa)), Ok(()));
+    let reversed = DFA::from_nfa(&NFA::from_regex("ab|cd").unwrap()).to_nfa().reverse();
+    let mut text = Vec::new();
+    FormalLanguage::from_nfa(&reversed).unwrap().write_grammer(&mut text).unwrap();
+    assert_eq!(read(&text).equivalent(&DFA::from_nfa(&NFA::from_regex("ba|dc").unwrap())), Ok(()));
+
+    let unwritable = |pattern| match FormalLanguage::from_nfa(&NFA::from_regex(pattern).unwrap()) {
+        Err(GrammarError { kind : GrammarErrorKind::UnwritableTerminal(ch), .. }) => Some(ch),
+        _ => None,
+    };
+    assert_eq!(unwritable("aB"), Some('B'));
+    assert_eq!(unwritable("a\\|"), Some('|'));
+    assert_eq!(unwritable("[^\"]"), Some('\t'));
+    let err = FormalLanguage::from_nfa(&NFA::from_regex("a b").unwrap()).err().unwrap();
+    assert_eq!(err.to_string(), "' ' can't be written as a terminal in ` `");
+
+    // an edge labelled with a nonterminal name is dropped
+    let mut nfa = NFA::new(0, 1);
+    nfa.push(0, (Token::Character('a'), 1));
+    nfa.push(0, (Token::Identifier("B".to_string()), 1));
+    let mut text = Vec::new();
+    FormalLanguage::from_nfa(&nfa).unwrap().write_grammer(&mut text).unwrap();
+    assert_eq!(String::from_utf8(text).unwrap(), "S -> aA\nA -> ε\n");
+    // a point whose only way to accepting is a nonterminal name is dropped too
+    let mut nfa = NFA::new(0, 1);
+    nfa.push(0, (Token::Character('a'), 2));
+    nfa.push(2, (Token::Identifier("B".to_string()), 1));
+    nfa.push(0, (Token::Character('b'), 1));
+    let mut text = Vec::new();
+    FormalLanguage::from_nfa(&nfa).unwrap().write_grammer(&mut text).unwrap();
+    assert_eq!(String::from_utf8(text.clone()).unwrap(), "S -> bA\nA -> ε\n");
+    assert_eq!(read(&text).equivalent(&DFA::from_nfa(&NFA::from_regex("b").unwrap())), Ok(()));
+
+    // the empty language is saved as an empty file, which reads back as accepting nothing
+    for edge in [Token::Character('a'), Token::Identifier("B".to_string())] {
+        let mut nfa = NFA::new(0, 1);
+        nfa.push(0, (edge, 2));
+        let mut text = Vec::new();
+        FormalLanguage::from_nfa(&nfa).unwrap().write_grammer(&mut text).unwrap();
+        assert!(text.is_empty());
+        assert!(read(&text).is_empty());
+    }
 }
@@ -0,0 +1,186 @@
+use std::ops::Range;
+use std::{error, fmt};
+use crate::dfa::DFA;
+use crate::nfa::NFA;
+use crate::regex::RegexError;
+
+/// builder of a Lexer, collecting the named patterns and their priorities
+#[derive(Default)]
+pub struct LexerBuilder {
+    rules : Vec<(String, String, i32)>,
+}
+
+impl LexerBuilder {
+    pub fn new() -> Self {
+        LexerBuilder {
+            rules : Vec::new(),
+        }
+    }
+
+    /// add a token kind `name` matching the regular expression `pattern`. when
+    /// several rules match the same longest text, the highest priority wins and
+    /// the rule added first wins between equal priorities
+    pub fn rule(mut self, name : &str, pattern : &str, priority : i32) -> Self {
+        self.rules.push((name.to_string(), pattern.to_string(), priority));
+        self
+    }
+
+    /// union the NFAs of every rule, labelling their accepting points with the rule
+    /// id, and determinize and minimize them into one DFA. fail with the first rule,
+    /// in insertion order, whose pattern isn't a valid regular expression
+    pub fn build(self) -> Result<Lexer, BuildError> {
+        let mut rules = Vec::new();
+        for (index, (name, pattern, priority)) in self.rules.into_iter().enumerate() {
+            match NFA::from_regex(&pattern) {
+                Ok(nfa) => rules.push((name, nfa, priority)),
+                Err(error) => return Err(BuildError { index, name, error }),
+            }
+        }
+
+        // rule ids follow the priority, since from_nfa keeps the smallest rule id
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.2));
+        let mut names = Vec::new();
+        let mut nfas = Vec::new();
+        for (rule, (name, mut nfa, _)) in rules.into_iter().enumerate() {
+            for id in nfa.get_accepting() {
+                nfa.add_accepting_rule(id, rule);
+            }
+            names.push(name);
+            nfas.push(nfa);
+        }
+
+        let mut dfa = DFA::from_nfa(&NFA::union(&nfas));
+        dfa.simplifier();
+        Ok(Lexer {
+            dfa,
+            names,
+        })
+    }
+}
+
+/// error returned by LexerBuilder::build, the rule added at `index` (0-based, in
+/// insertion order) and named `name` has an invalid pattern
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BuildError {
+    pub index : usize,
+    pub name : String,
+    pub error : RegexError,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rule {} `{}`: {}", self.index, self.name, self.error)
+    }
+}
+
+impl error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// maximal munch lexer over the minimized DFA of all of its rules
+pub struct Lexer {
+    dfa : DFA,
+    /// the name of every rule id
+    names : Vec<String>,
+}
+
+impl Lexer {
+    pub fn builder() -> LexerBuilder {
+        LexerBuilder::new()
+    }
+
+    pub fn get_dfa(&self) -> &DFA {
+        &self.dfa
+    }
+
+    /// split `input` into (kind, byte range) tokens, always taking the longest match.
+    /// fail at the first position where no rule matches a non-empty text
+    pub fn tokenize(&self, input : &str) -> Result<Vec<(&str, Range<usize>)>, LexError> {
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+        while pos < input.len() {
+            match self.dfa.match_prefix(&input[pos..]) {
+                Some((len, rule)) if len > 0 => {
+                    tokens.push((self.names[rule].as_str(), pos..pos + len));
+                    pos += len;
+                }
+                _ => return Err(LexError { offset : pos }),
+            }
+        }
+        Ok(tokens)
+    }
+}
+
+/// error returned by Lexer::tokenize, `offset` is the byte where no rule matches
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LexError {
+    pub offset : usize,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no rule matches at byte {}", self.offset)
+    }
+}
+
+impl error::Error for LexError {}
+
+#[test]
+fn test_lexer() {
+    let lexer = Lexer::builder()
+        .rule("ident", "[a-z_][a-z0-9_]*", 0)
+        .rule("number", "[0-9]+", 0)
+        .rule("if", "if", 1)
+        .rule("op", "==|=|<|<=", 0)
+        .rule("space", "[ \\n]+", 0)
+        .build()
+        .unwrap();
+    let input = "if iff <= 10\nx1=ifx";
+    let tokens : Vec<_> = lexer.tokenize(input)
+        .unwrap()
+        .into_iter()
+        .filter(|(kind, _)| *kind != "space")
+        .map(|(kind, range)| (kind, &input[range]))
+        .collect();
+    assert_eq!(tokens, vec![
+        ("if", "if"),
+        ("ident", "iff"),
+        ("op", "<="),
+        ("number", "10"),
+        ("ident", "x1"),
+        ("op", "="),
+        ("ident", "ifx"),
+    ]);
+    assert_eq!(lexer.tokenize("a + b").unwrap_err(), LexError { offset : 2 });
+
+    // the same priority falls back to the order the rules were added
+    let lexer = Lexer::builder()
+        .rule("first", "ab", 0)
+        .rule("second", "a[a-z]", 0)
+        .build()
+        .unwrap();
+    assert_eq!(lexer.tokenize("abac").unwrap(), vec![("first", 0..2), ("second", 2..4)]);
+
+    let lexer = Lexer::builder()
+        .rule("low", "a", i32::MIN)
+        .rule("high", "a", i32::MAX)
+        .build()
+        .unwrap();
+    assert_eq!(lexer.tokenize("a").unwrap(), vec![("high", 0..1)]);
+
+    // a bad pattern names its rule, even after a higher priority rule
+    let error = Lexer::builder()
+        .rule("number", "[0-9]+", 0)
+        .rule("group", "(ab", 0)
+        .rule("keyword", "if", 1)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(error.index, 1);
+    assert_eq!(error.name, "group");
+    assert_eq!(error.error.kind, crate::regex::RegexErrorKind::UnclosedGroup);
+    assert_eq!(error.to_string(), format!("rule 1 `group`: {}", error.error));
+    assert!(error::Error::source(&error).is_some());
+}
@@ -12,3 +12,5 @@ pub mod dfa;
 pub mod nfa;
 pub mod token;
 pub mod formal;
+pub mod regex;
+pub mod lexer;
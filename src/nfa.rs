@@ -1,13 +1,17 @@
-use std::collections::{HashMap, VecDeque, HashSet};
+use std::collections::{HashMap, VecDeque, HashSet, BTreeSet, BTreeMap};
 use std::borrow::Cow;
+use std::ops::Range;
 use crate::token::Token;
+use crate::regex::{Regex, RegexError};
 use dot;
 
-/// NFA data structure contained the start point id,
-/// and use the hashmap to map the point to a NFANode.
+/// NFA data structure contained the start point ids and the accepting point ids
+/// with their rule ids, and use the hashmap to map the point to a NFANode.
 pub struct NFA {
     start : u64,
-    termnial : u64,
+    /// all of the start points, `start` included
+    starts : BTreeSet<u64>,
+    accepting : BTreeMap<u64, usize>,
     nodes : HashMap<u64, NFANode>,
 }
 
@@ -15,25 +19,132 @@ type Nd = u64;
 type Ed = (u64, u64, Token);
 
 impl NFA {
+    /// create the NFA with `termnial` as its first accepting point,
+    /// more of them can be added by add_accepting
     pub fn new(start : u64, termnial : u64) -> Self {
+        let mut nfa = NFA::with_start(start);
+        nfa.add_accepting(termnial);
+        nfa
+    }
+
+    /// mark `nodeid` as one more start point, the NFA starts in all of them at once
+    pub fn add_start(&mut self, nodeid : u64) {
+        self.nodes.entry(nodeid).or_insert_with(|| NFANode::new(nodeid));
+        self.starts.insert(nodeid);
+    }
+
+    /// get all of the start point ids
+    pub fn get_starts(&self) -> &BTreeSet<u64> {
+        &self.starts
+    }
+
+    /// the sorted epsilon closure of all of the start points
+    pub fn get_start_closure(&self) -> Vec<u64> {
+        self.closure(self.starts.iter().cloned())
+            .into_iter()
+            .collect()
+    }
+
+    /// mark `nodeid` as an accepting point of rule 0
+    pub fn add_accepting(&mut self, nodeid : u64) {
+        self.add_accepting_rule(nodeid, 0);
+    }
+
+    /// mark `nodeid` as an accepting point of `rule`, DFA::from_nfa keeps the rule
+    /// in the DFANodeType so a Lexer can tell which pattern matched
+    pub fn add_accepting_rule(&mut self, nodeid : u64, rule : usize) {
+        self.nodes.entry(nodeid).or_insert_with(|| NFANode::new(nodeid));
+        self.accepting.insert(nodeid, rule);
+    }
+
+    pub fn is_accepting(&self, nodeid : u64) -> bool {
+        self.accepting.contains_key(&nodeid)
+    }
+
+    /// get the rule id of an accepting point
+    pub fn get_rule(&self, nodeid : u64) -> Option<usize> {
+        self.accepting.get(&nodeid).cloned()
+    }
+
+    /// get all of the accepting point ids
+    pub fn get_accepting(&self) -> BTreeSet<u64> {
+        self.accepting.keys().cloned().collect()
+    }
+
+    /// create the NFA accepting the union of `nfas`, with a new start point 0 and
+    /// epsilon edges to the renumbered starts of each of them. the rule ids of the
+    /// accepting points are kept
+    pub fn union(nfas : &[NFA]) -> Self {
+        let mut union = NFA::with_start(0);
+        let mut offset = 1;
+        for nfa in nfas {
+            for node in nfa.nodes.values() {
+                union.nodes.entry(node.nodeid + offset)
+                    .or_insert_with(|| NFANode::new(node.nodeid + offset));
+                for (token, to) in &node.edges {
+                    union.push(node.nodeid + offset, (token.clone(), to + offset));
+                }
+            }
+            for (id, rule) in &nfa.accepting {
+                union.add_accepting_rule(id + offset, *rule);
+            }
+            for start in &nfa.starts {
+                union.push(0, (Token::Epsilon, start + offset));
+            }
+            offset += nfa.nodes.keys().max().unwrap() + 1;
+        }
+        union
+    }
+    
+    /// create the NFA of the reversed language: every edge is flipped, the accepting
+    /// points become the start points and the start points become accepting points
+    /// of rule 0. the result has one start point for every accepting point, or a
+    /// new point accepting nothing if there is none
+    pub fn reverse(&self) -> Self {
+        let start = match self.accepting.keys().next() {
+            Some(start) => *start,
+            None => self.nodes.keys().max().unwrap() + 1,
+        };
+        let mut reversed = NFA::with_start(start);
+        for id in self.accepting.keys() {
+            reversed.add_start(*id);
+        }
+        for node in self.nodes.values() {
+            reversed.nodes.entry(node.nodeid).or_insert_with(|| NFANode::new(node.nodeid));
+            for (token, to) in &node.edges {
+                reversed.push(*to, (token.clone(), node.nodeid));
+            }
+        }
+        for id in &self.starts {
+            reversed.add_accepting(*id);
+        }
+        reversed
+    }
+
+    /// create the NFA with a single start point and no accepting point
+    pub(crate) fn with_start(start : u64) -> Self {
         let mut nodes = HashMap::new();
         nodes.insert(start, NFANode::new(start));
+        let mut starts = BTreeSet::new();
+        starts.insert(start);
         NFA {
             start,
-            termnial,
-            nodes
+            starts,
+            accepting : BTreeMap::new(),
+            nodes,
         }
     }
 
-    pub fn get_terminal(&self) -> u64 {
-        self.termnial
-    }
-    
-    /// get NFA.start
+    /// get NFA.start, the first start point
     pub fn get_start(&self) -> u64 {
         self.start 
     }
 
+    /// get all of NFA.nodes, in no particular order
+    pub fn get_nodes(&self) -> impl Iterator<Item = &NFANode> {
+        self.nodes.values()
+    }
+
     /// get NFA.nodes[nodeid]
     pub fn get_node(&self, nodeid : u64) -> Option<&NFANode> {
         self.nodes.get(&nodeid)
@@ -41,9 +152,7 @@ impl NFA {
 
     /// push the (from, (Token, to)) tuple to insert it into the NFA graph
     pub fn push(&mut self, nodeid : u64, tuple : (Token, u64)) {
-        if self.nodes.get(&tuple.1).is_none() {
-            self.nodes.insert(tuple.1, NFANode::new(nodeid));
-        }
+        self.nodes.entry(tuple.1).or_insert_with(|| NFANode::new(tuple.1));
         if let Some(node) = self.nodes.get_mut(&nodeid) {
             node.push(tuple);
         } else {
@@ -53,6 +162,94 @@ impl NFA {
         }
     }
 
+    /// build the NFA of `pattern` with Thompson construction, see Regex::parse
+    /// for the supported syntax
+    pub fn from_regex(pattern : &str) -> Result<Self, RegexError> {
+        Ok(NFA::from_regex_tree(&Regex::parse(pattern)?))
+    }
+
+    /// build the NFA of a parsed regular expression with Thompson construction.
+    /// every sub-expression gets its own start and end point, numbered in the
+    /// order they are created, so `a(b|c)*` gives points 0 to 9
+    pub fn from_regex_tree(regex : &Regex) -> Self {
+        let mut thompson = Thompson {
+            num : 0,
+            edges : Vec::new(),
+        };
+        let (start, end) = thompson.build(regex);
+        let mut nfa = NFA::new(start, end);
+        thompson.edges
+            .into_iter()
+            .for_each(|(from, token, to)| nfa.push(from, (token, to)));
+        nfa
+    }
+
+    /// convert the NFA into a regular expression by state elimination, None if it
+    /// accepts nothing. every point is eliminated in turn, cheapest first, and the
+    /// edges around it are joined with the simplifying Regex constructors
+    pub fn to_regex(&self) -> Option<Regex> {
+        let first = self.nodes.keys().max().unwrap() + 1;
+        let last = first + 1;
+        let mut edges : BTreeMap<(u64, u64), Regex> = BTreeMap::new();
+        let add = |edges : &mut BTreeMap<(u64, u64), Regex>, from, to, regex| {
+            let regex = match edges.remove(&(from, to)) {
+                Some(old) => Regex::alternate(old, regex),
+                None => regex,
+            };
+            edges.insert((from, to), regex);
+        };
+        for start in &self.starts {
+            add(&mut edges, first, *start, Regex::Epsilon);
+        }
+        for id in self.accepting.keys() {
+            add(&mut edges, *id, last, Regex::Epsilon);
+        }
+        let mut ids : Vec<&u64> = self.nodes.keys().collect();
+        ids.sort();
+        for id in ids {
+            for (token, to) in &self.nodes.get(id).unwrap().edges {
+                let regex = match token {
+                    Token::Epsilon => Regex::Epsilon,
+                    Token::Character(ch) => Regex::Character(*ch),
+                    Token::Class(class) => Regex::Class(class.clone()),
+                    // a nonterminal name matches no char
+                    Token::Identifier(_) => continue,
+                };
+                add(&mut edges, *id, *to, regex);
+            }
+        }
+
+        let mut remaining : BTreeSet<u64> = self.nodes.keys().cloned().collect();
+        while !remaining.is_empty() {
+            // the point with the fewest in * out edges adds the fewest new ones
+            let degree = |id : u64| {
+                let ins = edges.keys().filter(|(from, to)| *to == id && *from != id).count();
+                let outs = edges.keys().filter(|(from, to)| *from == id && *to != id).count();
+                ins * outs
+            };
+            let id = *remaining.iter().min_by_key(|id| degree(**id)).unwrap();
+            remaining.remove(&id);
+
+            let repeat = edges.remove(&(id, id)).map(Regex::star).unwrap_or(Regex::Epsilon);
+            let ins : Vec<(u64, Regex)> = edges.iter()
+                .filter(|((_, to), _)| *to == id)
+                .map(|((from, _), regex)| (*from, regex.clone()))
+                .collect();
+            let outs : Vec<(u64, Regex)> = edges.iter()
+                .filter(|((from, _), _)| *from == id)
+                .map(|((_, to), regex)| (*to, regex.clone()))
+                .collect();
+            edges.retain(|(from, to), _| *from != id && *to != id);
+            for (from, left) in &ins {
+                for (to, right) in &outs {
+                    let regex = Regex::concat(Regex::concat(left.clone(), repeat.clone()), right.clone());
+                    add(&mut edges, *from, *to, regex);
+                }
+            }
+        }
+        edges.remove(&(first, last))
+    }
+
     /// get all of the epsilon_closures of specified nodeid, which include itself.
     /// return type is Vector, so if you want to translate to other type, please use iter
     pub fn get_epsilon_closure_node(&self, nodeid : u64) -> Option<Vec<u64>> {
@@ -72,10 +269,9 @@ impl NFA {
                 .epsilon_closures()
                 .iter()
                 .map(|id| {
-                    if let None = visited.get(id) {
+                    if visited.insert(*id) {
                         ans.push(*id);
                         queue.push_back(*id);
-                        visited.insert(*id);
                     }
                 })
                 .collect();
@@ -83,6 +279,67 @@ impl NFA {
 
         Some(ans)
     }
+
+    /// whether the whole `string` is accepted, simulating the set of current points
+    /// instead of building a DFA
+    pub fn is_match(&self, string : &str) -> bool {
+        let mut states = self.closure(self.starts.iter().cloned());
+        for ch in string.chars() {
+            if states.is_empty() {
+                return false;
+            }
+            states = self.step(&states, ch);
+        }
+        states.iter().any(|id| self.is_accepting(*id))
+    }
+
+    /// find the leftmost-longest match in `string` and return its byte range,
+    /// a match may be empty if the NFA accepts the empty string
+    pub fn find(&self, string : &str) -> Option<Range<usize>> {
+        let starts = string.char_indices()
+            .map(|(i, _)| i)
+            .chain(Some(string.len()));
+        for start in starts {
+            let mut states = self.closure(self.starts.iter().cloned());
+            let mut end = None;
+            if states.iter().any(|id| self.is_accepting(*id)) {
+                end = Some(start);
+            }
+            for (i, ch) in string[start..].char_indices() {
+                states = self.step(&states, ch);
+                if states.is_empty() {
+                    break;
+                }
+                if states.iter().any(|id| self.is_accepting(*id)) {
+                    end = Some(start + i + ch.len_utf8());
+                }
+            }
+            if let Some(end) = end {
+                return Some(start..end);
+            }
+        }
+        None
+    }
+
+    /// the union of the epsilon closures of `nodeids`
+    fn closure<I>(&self, nodeids : I) -> BTreeSet<u64>
+    where
+        I : IntoIterator<Item = u64>
+    {
+        nodeids.into_iter()
+            .flat_map(|id| self.get_epsilon_closure_node(id).unwrap())
+            .collect()
+    }
+
+    /// the epsilon closed set of points reached from `states` on `ch`
+    fn step(&self, states : &BTreeSet<u64>, ch : char) -> BTreeSet<u64> {
+        let targets : Vec<u64> = states.iter()
+            .flat_map(|id| self.nodes.get(id).unwrap().edges.iter())
+            .filter(|(token, _)| token.matches(ch))
+            .map(|(_, to)| *to)
+            .collect();
+        self.closure(targets)
+    }
 }
 
 
@@ -116,13 +373,22 @@ impl<'a> dot::Labeller<'a, Nd, Ed> for NFA {
         dot::Id::new(format!("node{}", *n)).unwrap()
     }
 
-    fn edge_label(&self, ed : &Ed) -> dot::LabelText {
-        let s = match ed.2 {
+    fn node_shape(&'a self, n : &Nd) -> Option<dot::LabelText<'a>> {
+        if self.is_accepting(*n) {
+            Some(dot::LabelText::LabelStr(Cow::Borrowed("doublecircle")))
+        } else {
+            None
+        }
+    }
+
+    fn edge_label(&self, ed : &Ed) -> dot::LabelText<'_> {
+        let s = match &ed.2 {
             Token::Epsilon => "Epsilon".to_string(),
             Token::Character(ch) => ch.to_string(),
-            _ => unreachable!(),
+            Token::Identifier(name) => name.clone(),
+            Token::Class(class) => class.to_string(),
         };
-        dot::LabelText::LabelStr(Cow::Owned(format!("{}", s)))
+        dot::LabelText::LabelStr(Cow::Owned(s))
     }
 }
 
@@ -175,12 +441,75 @@ fn test_get_spsilion_closure_node() {
     println!("9 test result : {:?}", nfa.get_epsilon_closure_node(9));
 }
 
+/// state of the Thompson construction, `num` is the next free point id
+struct Thompson {
+    num : u64,
+    edges : Vec<(u64, Token, u64)>,
+}
+
+impl Thompson {
+    fn point(&mut self) -> u64 {
+        self.num += 1;
+        self.num - 1
+    }
+
+    /// build the fragment of `regex` and return its (start, end) points
+    fn build(&mut self, regex : &Regex) -> (u64, u64) {
+        match regex {
+            Regex::Epsilon | Regex::Character(_) | Regex::Class(_) => {
+                let token = match regex {
+                    Regex::Character(ch) => Token::Character(*ch),
+                    Regex::Class(class) => Token::Class(class.clone()),
+                    _ => Token::Epsilon,
+                };
+                let (start, end) = (self.point(), self.point());
+                self.edges.push((start, token, end));
+                (start, end)
+            }
+            Regex::Concat(left, right) => {
+                let (start, left_end) = self.build(left);
+                let (right_start, end) = self.build(right);
+                self.edges.push((left_end, Token::Epsilon, right_start));
+                (start, end)
+            }
+            Regex::Alternate(left, right) => {
+                let start = self.point();
+                let (left_start, left_end) = self.build(left);
+                let (right_start, right_end) = self.build(right);
+                let end = self.point();
+                self.edges.push((start, Token::Epsilon, left_start));
+                self.edges.push((start, Token::Epsilon, right_start));
+                self.edges.push((left_end, Token::Epsilon, end));
+                self.edges.push((right_end, Token::Epsilon, end));
+                (start, end)
+            }
+            Regex::Star(inner) | Regex::Plus(inner) | Regex::Optional(inner) => {
+                let start = self.point();
+                let (inner_start, inner_end) = self.build(inner);
+                let end = self.point();
+                self.edges.push((start, Token::Epsilon, inner_start));
+                if let Regex::Star(_) | Regex::Optional(_) = regex {
+                    self.edges.push((start, Token::Epsilon, end));
+                }
+                if let Regex::Star(_) | Regex::Plus(_) = regex {
+                    self.edges.push((inner_end, Token::Epsilon, inner_start));
+                }
+                self.edges.push((inner_end, Token::Epsilon, end));
+                (start, end)
+            }
+        }
+    }
+}
+
 pub struct NFANode {
     nodeid : u64,
     edges : Vec<(Token, u64)>,
 }
 
 impl NFANode {
+    pub fn get_id(&self) -> u64 {
+        self.nodeid
+    }
     pub fn get_edges(&self) -> &Vec<(Token, u64)> {
         &self.edges
     }
@@ -208,3 +537,20 @@ impl NFANode {
     }
 }
 
+
+#[test]
+fn test_nfa_to_regex() {
+    for pattern in &["ab*c|d", "(a|b)*abb", "a?(bc)+|[x-z]*", "ε", "(a*b*)*c"] {
+        let nfa = NFA::from_regex(pattern).unwrap();
+        let regex = nfa.to_regex().unwrap().to_string();
+        let back = NFA::from_regex(&regex).unwrap();
+        for word in &["", "a", "abc", "d", "abbc", "babb", "aabb", "bcbc", "xzy", "abca", "c", "abbac", "ac"] {
+            assert_eq!(nfa.is_match(word), back.is_match(word), "{} {} {}", pattern, regex, word);
+        }
+    }
+    assert_eq!(NFA::from_regex("a|a").unwrap().to_regex().unwrap().to_string(), "a");
+
+    let mut nfa = NFA::new(0, 1);
+    nfa.push(0, (Token::Identifier("A".to_string()), 1));
+    assert_eq!(nfa.to_regex(), None);
+}
@@ -0,0 +1,459 @@
+use std::{error, fmt};
+use crate::token::CharClass;
+
+/// syntax tree of a regular expression, built by Regex::parse
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Regex {
+    Epsilon,
+    Character(char),
+    Class(CharClass),
+    Concat(Box<Regex>, Box<Regex>),
+    Alternate(Box<Regex>, Box<Regex>),
+    Star(Box<Regex>),
+    Plus(Box<Regex>),
+    Optional(Box<Regex>),
+}
+
+impl Regex {
+    /// parse `pattern`, which supports concatenation, `|`, `*`, `+`, `?`, grouping
+    /// with `()`, `ε`, escapes like `\*` or `\u{1F600}` and classes like `[a-z0-9]`, `[^"]` or `.`,
+    /// where `.` matches every char. an empty pattern or group is `ε`
+    pub fn parse(pattern : &str) -> Result<Regex, RegexError> {
+        let mut parser = Parser {
+            chars : pattern.chars().collect(),
+            pos : 0,
+        };
+        let regex = parser.alternate()?;
+        match parser.peek() {
+            None => Ok(regex),
+            Some(_) => Err(RegexError::new(parser.pos + 1, RegexErrorKind::UnmatchedParen)),
+        }
+    }
+
+    /// `left` then `right`, where `ε` is absorbed and `aa*` or `a*a` becomes `a+`
+    pub fn concat(left : Regex, right : Regex) -> Regex {
+        match (left, right) {
+            (Regex::Epsilon, regex) | (regex, Regex::Epsilon) => regex,
+            (left, Regex::Star(inner)) if left == *inner => Regex::Plus(inner),
+            (Regex::Star(inner), right) if right == *inner => Regex::Plus(inner),
+            (left, right) => Regex::Concat(Box::new(left), Box::new(right)),
+        }
+    }
+
+    /// `left` or `right`. the alternatives of both are flattened, repeated ones are
+    /// dropped, single chars and classes are merged into one class, and an `ε`
+    /// alternative makes the rest optional, so `a|a` is `a` and `ε|a+` is `a*`
+    pub fn alternate(left : Regex, right : Regex) -> Regex {
+        let mut alternatives = Vec::new();
+        left.push_alternatives(&mut alternatives);
+        right.push_alternatives(&mut alternatives);
+
+        let mut nullable = false;
+        let mut ranges = Vec::new();
+        let mut items : Vec<Regex> = Vec::new();
+        for regex in alternatives {
+            match regex {
+                Regex::Epsilon => nullable = true,
+                Regex::Character(ch) => ranges.push((ch, ch)),
+                Regex::Class(class) => ranges.extend(class.ranges().iter().cloned()),
+                regex => {
+                    if !items.contains(&regex) {
+                        items.push(regex);
+                    }
+                }
+            }
+        }
+        // the merged class takes the place of the first char or class
+        let class = CharClass::new(ranges);
+        if !class.is_empty() {
+            let regex = match class.ranges() {
+                [(start, end)] if start == end => Regex::Character(*start),
+                _ => Regex::Class(class),
+            };
+            items.insert(0, regex);
+        }
+
+        let regex = items.into_iter()
+            .reduce(|left, right| Regex::Alternate(Box::new(left), Box::new(right)));
+        match (regex, nullable) {
+            (None, _) => Regex::Epsilon,
+            (Some(regex), false) => regex,
+            (Some(Regex::Plus(inner)), true) => Regex::Star(inner),
+            (Some(regex), true) if regex.is_nullable() => regex,
+            (Some(regex), true) => Regex::Optional(Box::new(regex)),
+        }
+    }
+
+    /// `regex` repeated any times, where `ε*` is `ε` and `(a*)*`, `(a+)*` or `(a?)*`
+    /// are `a*`
+    pub fn star(regex : Regex) -> Regex {
+        match regex {
+            Regex::Epsilon => Regex::Epsilon,
+            Regex::Star(inner) | Regex::Plus(inner) | Regex::Optional(inner) => Regex::Star(inner),
+            regex => Regex::Star(Box::new(regex)),
+        }
+    }
+
+    /// whether the empty string is matched
+    pub fn is_nullable(&self) -> bool {
+        match self {
+            Regex::Epsilon | Regex::Star(_) | Regex::Optional(_) => true,
+            Regex::Character(_) | Regex::Class(_) => false,
+            Regex::Concat(left, right) => left.is_nullable() && right.is_nullable(),
+            Regex::Alternate(left, right) => left.is_nullable() || right.is_nullable(),
+            Regex::Plus(inner) => inner.is_nullable(),
+        }
+    }
+
+    /// push the alternatives of a flattened alternation, `a?` counts as `a|ε`
+    fn push_alternatives(self, alternatives : &mut Vec<Regex>) {
+        match self {
+            Regex::Alternate(left, right) => {
+                left.push_alternatives(alternatives);
+                right.push_alternatives(alternatives);
+            }
+            Regex::Optional(inner) => {
+                inner.push_alternatives(alternatives);
+                alternatives.push(Regex::Epsilon);
+            }
+            regex => alternatives.push(regex),
+        }
+    }
+
+    /// write the regex with the fewest parentheses needed at `level`, which is 0 for
+    /// an alternative, 1 for a part of a concatenation and 2 for a repeated regex
+    fn write(&self, f : &mut fmt::Formatter, level : u8) -> fmt::Result {
+        let own = match self {
+            Regex::Alternate(..) => 0,
+            Regex::Concat(..) => 1,
+            _ => 2,
+        };
+        if own < level {
+            write!(f, "(")?;
+        }
+        match self {
+            Regex::Epsilon => write!(f, "ε")?,
+            Regex::Character(ch) => match ch {
+                '|' | '*' | '+' | '?' | '(' | ')' | '[' | ']' | '.' | '\\' | 'ε' => write!(f, "\\{}", ch)?,
+                '\n' => write!(f, "\\n")?,
+                '\t' => write!(f, "\\t")?,
+                '\r' => write!(f, "\\r")?,
+                _ if ch.is_control() => write!(f, "{}", ch.escape_default())?,
+                _ => write!(f, "{}", ch)?,
+            },
+            // a class of most chars reads better as the negation of the rest
+            Regex::Class(class) if class.contains('\0') && *class != CharClass::any() => {
+                let negated = class.negate().to_string();
+                write!(f, "[^{}", &negated[1..])?;
+            }
+            Regex::Class(class) => write!(f, "{}", class)?,
+            Regex::Concat(left, right) => {
+                left.write(f, 1)?;
+                right.write(f, 1)?;
+            }
+            Regex::Alternate(left, right) => {
+                left.write(f, 0)?;
+                write!(f, "|")?;
+                right.write(f, 0)?;
+            }
+            Regex::Star(inner) | Regex::Plus(inner) | Regex::Optional(inner) => {
+                inner.write(f, 2)?;
+                let op = match self {
+                    Regex::Star(_) => '*',
+                    Regex::Plus(_) => '+',
+                    _ => '?',
+                };
+                write!(f, "{}", op)?;
+            }
+        }
+        if own < level {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+/// the pattern of the regex, which Regex::parse reads back
+impl fmt::Display for Regex {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+/// recursive descent parser, every level returns on the first char it doesn't know
+struct Parser {
+    chars : Vec<char>,
+    pos : usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn alternate(&mut self) -> Result<Regex, RegexError> {
+        let mut regex = self.concat()?;
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            let right = self.concat()?;
+            regex = Regex::Alternate(Box::new(regex), Box::new(right));
+        }
+        Ok(regex)
+    }
+
+    fn concat(&mut self) -> Result<Regex, RegexError> {
+        let mut regex = None;
+        while let Some(ch) = self.peek() {
+            if ch == '|' || ch == ')' {
+                break;
+            }
+            let right = self.repeat()?;
+            regex = Some(match regex {
+                Some(left) => Regex::Concat(Box::new(left), Box::new(right)),
+                None => right,
+            });
+        }
+        Ok(regex.unwrap_or(Regex::Epsilon))
+    }
+
+    fn repeat(&mut self) -> Result<Regex, RegexError> {
+        let mut regex = self.atom()?;
+        while let Some(ch) = self.peek() {
+            regex = match ch {
+                '*' => Regex::Star(Box::new(regex)),
+                '+' => Regex::Plus(Box::new(regex)),
+                '?' => Regex::Optional(Box::new(regex)),
+                _ => break,
+            };
+            self.pos += 1;
+        }
+        Ok(regex)
+    }
+
+    fn atom(&mut self) -> Result<Regex, RegexError> {
+        let column = self.pos + 1;
+        let ch = self.peek().expect("atom is only parsed before a char");
+        self.pos += 1;
+        match ch {
+            '(' => {
+                let regex = self.alternate()?;
+                if self.peek() != Some(')') {
+                    return Err(RegexError::new(column, RegexErrorKind::UnclosedGroup));
+                }
+                self.pos += 1;
+                Ok(regex)
+            }
+            '[' => self.class(column),
+            '*' | '+' | '?' => Err(RegexError::new(column, RegexErrorKind::NothingToRepeat)),
+            'ε' => Ok(Regex::Epsilon),
+            '.' => Ok(Regex::Class(CharClass::any())),
+            '\\' => Ok(Regex::Character(self.escape(column)?)),
+            _ => Ok(Regex::Character(ch)),
+        }
+    }
+
+    /// parse the char after a `\` at `column`
+    fn escape(&mut self, column : usize) -> Result<char, RegexError> {
+        let escaped = match self.peek() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('u') if self.chars.get(self.pos + 1) == Some(&'{') => return self.unicode(column),
+            Some(escaped) => escaped,
+            None => return Err(RegexError::new(column, RegexErrorKind::TrailingEscape)),
+        };
+        self.pos += 1;
+        Ok(escaped)
+    }
+
+    /// parse a `u{…}` escape of hex digits after the `\` at `column`
+    fn unicode(&mut self, column : usize) -> Result<char, RegexError> {
+        let digits : String = self.chars[self.pos + 2..]
+            .iter()
+            .take_while(|ch| **ch != '}')
+            .collect();
+        let end = self.pos + 2 + digits.chars().count();
+        let escaped = match self.chars.get(end) {
+            Some('}') if !digits.is_empty() && digits.len() <= 6 => {
+                u32::from_str_radix(&digits, 16).ok().and_then(std::char::from_u32)
+            }
+            _ => None,
+        };
+        match escaped {
+            Some(escaped) => {
+                self.pos = end + 1;
+                Ok(escaped)
+            }
+            None => Err(RegexError::new(column, RegexErrorKind::InvalidEscape)),
+        }
+    }
+
+    /// parse the rest of a class whose `[` is at `column`
+    fn class(&mut self, column : usize) -> Result<Regex, RegexError> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = Vec::new();
+        loop {
+            let start_column = self.pos + 1;
+            let start = match self.class_char()? {
+                Some(start) => start,
+                None => break,
+            };
+            let mut end = start;
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') {
+                self.pos += 1;
+                end = match self.class_char()? {
+                    Some(end) => end,
+                    None => return Err(RegexError::new(column, RegexErrorKind::UnclosedClass)),
+                };
+                if end < start {
+                    return Err(RegexError::new(start_column, RegexErrorKind::InvalidRange));
+                }
+            }
+            ranges.push((start, end));
+        }
+        if self.peek() != Some(']') {
+            return Err(RegexError::new(column, RegexErrorKind::UnclosedClass));
+        }
+        self.pos += 1;
+
+        let class = CharClass::new(ranges);
+        Ok(Regex::Class(if negated { class.negate() } else { class }))
+    }
+
+    /// parse one char of a class, return None at the closing `]` or the end
+    fn class_char(&mut self) -> Result<Option<char>, RegexError> {
+        let column = self.pos + 1;
+        match self.peek() {
+            None | Some(']') => Ok(None),
+            Some('\\') => {
+                self.pos += 1;
+                self.escape(column).map(Some)
+            }
+            Some(ch) => {
+                self.pos += 1;
+                Ok(Some(ch))
+            }
+        }
+    }
+}
+
+/// the reason a regular expression was rejected
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RegexErrorKind {
+    /// a `(` without its `)`
+    UnclosedGroup,
+    /// a `)` without its `(`
+    UnmatchedParen,
+    /// `*`, `+` or `?` with nothing before it
+    NothingToRepeat,
+    /// the pattern ends with a single `\`
+    TrailingEscape,
+    /// a `\u{…}` escape whose hex digits are not a char
+    InvalidEscape,
+    /// a `[` without its `]`
+    UnclosedClass,
+    /// a range like `z-a` whose start is greater than its end
+    InvalidRange,
+}
+
+/// error returned by Regex::parse, `column` is the 1-based char position in the pattern
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RegexError {
+    pub column : usize,
+    pub kind : RegexErrorKind,
+}
+
+impl RegexError {
+    fn new(column : usize, kind : RegexErrorKind) -> Self {
+        RegexError {
+            column,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self.kind {
+            RegexErrorKind::UnclosedGroup => "unclosed `(`",
+            RegexErrorKind::UnmatchedParen => "unmatched `)`",
+            RegexErrorKind::NothingToRepeat => "nothing to repeat",
+            RegexErrorKind::TrailingEscape => "trailing `\\`",
+            RegexErrorKind::InvalidEscape => "invalid `\\u{…}` escape",
+            RegexErrorKind::UnclosedClass => "unclosed `[`",
+            RegexErrorKind::InvalidRange => "invalid range",
+        };
+        write!(f, "column {}: {}", self.column, reason)
+    }
+}
+
+impl error::Error for RegexError {}
+
+#[test]
+fn test_parse_regex() {
+    use Regex::*;
+    let ch = |c| Box::new(Character(c));
+    assert_eq!(
+        Regex::parse("a(b|c)*").unwrap(),
+        Concat(ch('a'), Box::new(Star(Box::new(Alternate(ch('b'), ch('c'))))))
+    );
+    assert_eq!(
+        Regex::parse("ab+|c?").unwrap(),
+        Alternate(Box::new(Concat(ch('a'), Box::new(Plus(ch('b'))))), Box::new(Optional(ch('c'))))
+    );
+    assert_eq!(Regex::parse("\\*ε").unwrap(), Concat(ch('*'), Box::new(Epsilon)));
+    assert_eq!(Regex::parse("()").unwrap(), Epsilon);
+
+    assert_eq!(Regex::parse("a(b").unwrap_err(), RegexError::new(2, RegexErrorKind::UnclosedGroup));
+    assert_eq!(Regex::parse("ab)").unwrap_err(), RegexError::new(3, RegexErrorKind::UnmatchedParen));
+    assert_eq!(Regex::parse("a|*").unwrap_err(), RegexError::new(3, RegexErrorKind::NothingToRepeat));
+    assert_eq!(Regex::parse("a\\").unwrap_err(), RegexError::new(2, RegexErrorKind::TrailingEscape));
+}
+
+#[test]
+fn test_parse_class() {
+    let class = |ranges : Vec<(char, char)>| Regex::Class(CharClass::new(ranges));
+    assert_eq!(Regex::parse("[a-z0-9_]").unwrap(), class(vec![('a', 'z'), ('0', '9'), ('_', '_')]));
+    assert_eq!(Regex::parse("[-a\\]-]").unwrap(), class(vec![('-', '-'), ('a', 'a'), (']', ']')]));
+    assert_eq!(Regex::parse("[^\"]").unwrap(), Regex::Class(CharClass::new(vec![('"', '"')]).negate()));
+    assert_eq!(Regex::parse(".").unwrap(), Regex::Class(CharClass::any()));
+
+    assert_eq!(Regex::parse("[a-").unwrap_err(), RegexError::new(1, RegexErrorKind::UnclosedClass));
+    assert_eq!(Regex::parse("x[ab").unwrap_err(), RegexError::new(2, RegexErrorKind::UnclosedClass));
+    assert_eq!(Regex::parse("[z-a]").unwrap_err(), RegexError::new(2, RegexErrorKind::InvalidRange));
+}
+
+#[test]
+fn test_simplify_regex() {
+    use Regex::*;
+    let ch = Character;
+    assert_eq!(Regex::alternate(ch('a'), ch('a')), ch('a'));
+    assert_eq!(Regex::alternate(ch('a'), ch('b')), Class(CharClass::new(vec![('a', 'b')])));
+    assert_eq!(Regex::star(Regex::star(ch('a'))), Star(Box::new(ch('a'))));
+    assert_eq!(Regex::concat(Epsilon, ch('a')), ch('a'));
+    assert_eq!(Regex::concat(ch('a'), Regex::star(ch('a'))), Plus(Box::new(ch('a'))));
+    assert_eq!(Regex::alternate(Epsilon, Plus(Box::new(ch('a')))), Star(Box::new(ch('a'))));
+    assert_eq!(Regex::alternate(Epsilon, Epsilon), Epsilon);
+    let ab = Regex::concat(ch('a'), ch('b'));
+    assert_eq!(Regex::alternate(Regex::alternate(ab.clone(), Epsilon), ab.clone()), Optional(Box::new(ab)));
+
+    for pattern in &["a(b|cd)*", "(ab|c)?d+", "\\*\\|a.", "[^\"]*", "(a|ε)(b*)*", "[a-c]|x|ab"] {
+        let regex = Regex::parse(pattern).unwrap();
+        assert_eq!(Regex::parse(&regex.to_string()).unwrap(), regex, "{}", pattern);
+    }
+    assert_eq!(Regex::parse("a(b|cd)*").unwrap().to_string(), "a(b|cd)*");
+    assert_eq!(Regex::parse("[^\"]").unwrap().to_string(), "[^\"]");
+
+    // control chars are written as `\u{…}` escapes and read back
+    assert_eq!(Regex::parse("\\u{1}[\\u{2}-\\u{3}]").unwrap(), Regex::concat(Character('\u{1}'), Class(CharClass::new(vec![('\u{2}', '\u{3}')]))));
+    for pattern in &["[^\\u{1}]", "[\\u{1}-\\u{3}]x", "\\u{7f}*|\\u{0}", "[\\u{0}-a]"] {
+        let regex = Regex::parse(pattern).unwrap();
+        assert_eq!(Regex::parse(&regex.to_string()).unwrap(), regex, "{} {}", pattern, regex);
+    }
+    assert_eq!(Regex::parse("a\\u{110000}").unwrap_err(), RegexError::new(2, RegexErrorKind::InvalidEscape));
+    assert_eq!(Regex::parse("\\u{12").unwrap_err(), RegexError::new(1, RegexErrorKind::InvalidEscape));
+    assert_eq!(Regex::parse("\\u1").unwrap(), Regex::concat(Character('u'), Character('1')));
+}
@@ -1,8 +1,207 @@
 use std::cmp::{Ord, PartialOrd};
+use std::fmt;
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum Token {
     Epsilon,
     Character(char),
-    Identifier(char),
+    Identifier(String),
+    Class(CharClass),
+}
+
+impl Token {
+    /// the token of the chars from `start` to `end`, a single char is a Token::Character
+    pub fn from_range(start : char, end : char) -> Self {
+        if start == end {
+            Token::Character(start)
+        } else {
+            Token::Class(CharClass::new(vec![(start, end)]))
+        }
+    }
+
+    /// the token of the union of `ranges`, a single char is a Token::Character
+    pub fn from_ranges(ranges : Vec<(char, char)>) -> Self {
+        match ranges.as_slice() {
+            [(start, end)] => Token::from_range(*start, *end),
+            _ => Token::Class(CharClass::new(ranges)),
+        }
+    }
+
+    /// whether an edge labelled with this token can be taken on `ch`
+    pub fn matches(&self, ch : char) -> bool {
+        match self {
+            Token::Character(c) => *c == ch,
+            Token::Class(class) => class.contains(ch),
+            _ => false,
+        }
+    }
+
+    /// the chars an edge labelled with this token can be taken on, as sorted ranges
+    pub fn ranges(&self) -> Vec<(char, char)> {
+        match self {
+            Token::Character(ch) => vec![(*ch, *ch)],
+            Token::Class(class) => class.ranges().to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// a set of chars stored as sorted, disjoint and non-adjacent ranges
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct CharClass {
+    ranges : Vec<(char, char)>,
+}
+
+impl CharClass {
+    /// build the class of the union of `ranges`, a range whose start is greater
+    /// than its end is empty
+    pub fn new(mut ranges : Vec<(char, char)>) -> Self {
+        ranges.retain(|(start, end)| start <= end);
+        ranges.sort();
+        let mut merged : Vec<(char, char)> = Vec::new();
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if char_index(start) <= char_index(last.1) + 1 => {
+                    last.1 = last.1.max(end);
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+        CharClass {
+            ranges : merged,
+        }
+    }
+
+    /// the class of every char, which is `.` in a regular expression
+    pub fn any() -> Self {
+        CharClass::new(vec![('\0', char::MAX)])
+    }
+
+    /// every char which is not in this class
+    pub fn negate(&self) -> Self {
+        let mut ranges = Vec::new();
+        let mut next = 0;
+        for (start, end) in &self.ranges {
+            if char_index(*start) > next {
+                ranges.push((index_char(next), index_char(char_index(*start) - 1)));
+            }
+            next = char_index(*end) + 1;
+        }
+        if next <= char_index(char::MAX) {
+            ranges.push((index_char(next), char::MAX));
+        }
+        CharClass {
+            ranges,
+        }
+    }
+
+    pub fn contains(&self, ch : char) -> bool {
+        self.ranges
+            .binary_search_by(|(start, end)| {
+                if *end < ch {
+                    std::cmp::Ordering::Less
+                } else if *start > ch {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+impl fmt::Display for CharClass {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        if *self == CharClass::any() {
+            return write!(f, ".");
+        }
+        let escape = |ch : char| match ch {
+            '\\' | ']' | '[' | '-' | '^' => format!("\\{}", ch),
+            _ if ch.is_control() => ch.escape_default().to_string(),
+            _ => ch.to_string(),
+        };
+        write!(f, "[")?;
+        for (start, end) in &self.ranges {
+            if start == end {
+                write!(f, "{}", escape(*start))?;
+            } else {
+                write!(f, "{}-{}", escape(*start), escape(*end))?;
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+/// split the chars of `tokens` into disjoint ranges, so the chars of every token are
+/// a union of some of the returned ranges. chars no token matches are left out
+pub fn split_ranges<'a, I>(tokens : I) -> Vec<(char, char)>
+where
+    I : IntoIterator<Item = &'a Token>
+{
+    // +1 where a range starts and -1 after it ends, swept in order of the index
+    let mut events : Vec<(u32, i64)> = tokens.into_iter()
+        .flat_map(|token| token.ranges())
+        .flat_map(|(start, end)| vec![(char_index(start), 1), (char_index(end) + 1, -1)])
+        .collect();
+    events.sort();
+
+    let mut ranges = Vec::new();
+    let mut count = 0;
+    for (i, (index, delta)) in events.iter().enumerate() {
+        count += delta;
+        match events.get(i + 1) {
+            Some((next, _)) if next > index && count > 0 => {
+                ranges.push((index_char(*index), index_char(next - 1)));
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+/// position of `ch` among all chars, skipping the surrogate gap, so consecutive
+/// chars have consecutive indexes
+pub fn char_index(ch : char) -> u32 {
+    let code = ch as u32;
+    if code < 0xD800 {
+        code
+    } else {
+        code - 0x800
+    }
+}
+
+/// the char at the index returned by char_index
+pub fn index_char(index : u32) -> char {
+    let code = if index < 0xD800 { index } else { index + 0x800 };
+    std::char::from_u32(code).expect("char index out of range")
+}
+
+#[test]
+fn test_char_class() {
+    let class = CharClass::new(vec![('a', 'f'), ('0', '9'), ('g', 'k'), ('x', 'x')]);
+    assert_eq!(class.ranges(), &[('0', '9'), ('a', 'k'), ('x', 'x')]);
+    assert!(class.contains('h') && class.contains('x') && !class.contains('l'));
+    assert_eq!(class.to_string(), "[0-9a-kx]");
+
+    let negated = class.negate();
+    assert!(!negated.contains('5') && negated.contains('l') && negated.contains('\u{E000}'));
+    assert_eq!(negated.negate(), class);
+    assert_eq!(CharClass::any().negate().ranges(), &[]);
+    assert_eq!(CharClass::new(vec![('\u{D7FF}', '\u{D7FF}'), ('\u{E000}', '\u{E001}')]).ranges().len(), 1);
+
+    let tokens = vec![
+        Token::Class(CharClass::new(vec![('a', 'z')])),
+        Token::Character('m'),
+        Token::Class(CharClass::new(vec![('x', '}')])),
+        Token::Epsilon,
+    ];
+    assert_eq!(split_ranges(&tokens), vec![('a', 'l'), ('m', 'm'), ('n', 'w'), ('x', 'z'), ('{', '}')]);
 }